@@ -0,0 +1,53 @@
+use std::fmt::Display;
+
+use bevy::log::error;
+
+/// Reads the asset at `path`, relative to the working directory, and parses it with `parse`.
+///
+/// Every asset also ships inside the binary, so instead of failing, a missing or broken file is
+/// logged as the `what` it holds and `None` is returned, leaving the caller on its built-in copy.
+pub fn load_asset<T, E: Display>(
+    path: &str,
+    what: &str,
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Option<T> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(error) => {
+            error!("failed to read {path}, using the default {what}: {error}");
+            return None;
+        }
+    };
+
+    match parse(&source) {
+        Ok(loaded) => Some(loaded),
+        Err(error) => {
+            error!("failed to load {path}, using the default {what}: {error}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::load_asset;
+
+    #[test]
+    pub fn load_asset_test() {
+        let path = env::temp_dir().join(format!("voxel-asset-{}.ron", std::process::id()));
+        let path = path.to_str().unwrap();
+        let parse = |source: &str| source.trim().parse::<u32>();
+
+        assert_eq!(load_asset(path, "numbers", parse), None);
+
+        std::fs::write(path, "42\n").unwrap();
+        assert_eq!(load_asset(path, "numbers", parse), Some(42));
+
+        std::fs::write(path, "forty two").unwrap();
+        assert_eq!(load_asset(path, "numbers", parse), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -4,21 +4,24 @@ use bevy::{
     input::mouse::MouseMotion,
     pbr::wireframe::WireframeConfig,
     prelude::{
-        Camera, Component, EulerRot, EventReader, Input, KeyCode, MouseButton, Quat, Query, Res,
-        ResMut, StageLabel, Transform, Vec2, Vec3, With,
+        Camera, Commands, Component, EulerRot, EventReader, IVec3, Quat, Query, Res, ResMut,
+        StageLabel, Transform, Vec2, Vec3, With,
     },
     text::Text,
     time::Time,
 };
-use ndshape::ConstShape;
-use rand::Rng;
+use leafwing_input_manager::prelude::ActionState;
 
 use crate::{
     chunk::{
-        container::{self, loaded::LoadedChunks, Chunks, DomainChunk},
-        ChunkShape, NoiseShape, X_SIZE, X_SIZE_U32, Y_SIZE_U32, Z_SIZE, Z_SIZE_U32,
+        container::{self, loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk},
+        generation::GenerationJobs,
+        meshing::MeshingJobs,
+        pending::PendingWrites,
     },
-    terrain::{self, noise::NoiseData, DebugTerrainGenerator},
+    player::keybinds::Action,
+    terrain::ore::OreStatistics,
+    world::WorldStorage,
     PosText,
 };
 
@@ -27,22 +30,20 @@ pub struct CameraController {
     pub enabled: bool,
     pub initialized: bool,
     pub sensitivity: f32,
-    pub key_forward: KeyCode,
-    pub key_back: KeyCode,
-    pub key_left: KeyCode,
-    pub key_right: KeyCode,
-    pub key_up: KeyCode,
-    pub key_down: KeyCode,
-    pub key_run: KeyCode,
-    pub mouse_key_enable_mouse: MouseButton,
-    pub keyboard_key_enable_mouse: KeyCode,
+    /// Radians per second the camera turns at with a gamepad stick pushed all the way.
+    pub stick_sensitivity: f32,
     pub walk_speed: f32,
     pub run_speed: f32,
+    pub reach: f32,
+    pub place_block: u8,
+    /// Flies freely through the world, otherwise the camera walks with a
+    /// [`PlayerBody`](crate::player::physics::PlayerBody) that collides with solid blocks.
+    pub flying: bool,
     pub friction: f32,
     pub pitch: f32,
     pub yaw: f32,
     pub velocity: Vec3,
-    pub last_chunk_pos: Option<(i32, i32)>,
+    pub last_chunk_pos: Option<ChunkKey>,
 }
 
 impl Default for CameraController {
@@ -51,17 +52,12 @@ impl Default for CameraController {
             enabled: true,
             initialized: false,
             sensitivity: 0.5,
-            key_forward: KeyCode::W,
-            key_back: KeyCode::S,
-            key_left: KeyCode::A,
-            key_right: KeyCode::D,
-            key_up: KeyCode::E,
-            key_down: KeyCode::Q,
-            key_run: KeyCode::LShift,
-            mouse_key_enable_mouse: MouseButton::Left,
-            keyboard_key_enable_mouse: KeyCode::M,
+            stick_sensitivity: 2.5,
             walk_speed: 60.0,
             run_speed: 120.0,
+            reach: 32.0,
+            place_block: 2,
+            flying: true,
             friction: 0.5,
             pitch: 0.0,
             yaw: 0.0,
@@ -74,9 +70,14 @@ impl Default for CameraController {
 #[derive(StageLabel)]
 pub struct CameraStage;
 
+/// Chunks loaded around the camera along the x and z axes.
+pub const RENDER_DISTANCE: i32 = 8;
+/// Chunks loaded above and below the camera.
+pub const VERTICAL_RENDER_DISTANCE: i32 = 2;
+
 pub fn camera_controller(
     time: Res<Time>,
-    key_input: Res<Input<KeyCode>>,
+    actions: Res<ActionState<Action>>,
     mut query: Query<(&mut Transform, &mut CameraController), With<Camera>>,
     mut text: Query<&mut Text, With<PosText>>,
     mut wireframe_config: ResMut<WireframeConfig>,
@@ -94,41 +95,30 @@ pub fn camera_controller(
             return;
         }
 
-        // Handle key input
-        let mut axis_input = Vec3::ZERO;
-
-        for (key, axis) in [
-            (options.key_forward, &mut axis_input.z),
-            (options.key_right, &mut axis_input.x),
-            (options.key_up, &mut axis_input.y),
-        ] {
-            if key_input.pressed(key) {
-                *axis += 1.0;
-            }
-        }
+        // Handle action input, sticks give partial movement so only clamp to unit length
+        let movement = actions
+            .axis_pair(Action::Move)
+            .map_or(Vec2::ZERO, |axis| axis.xy());
+        let vertical = actions.pressed(Action::Up) as i32 - actions.pressed(Action::Down) as i32;
+        let axis_input = Vec3::new(movement.x, vertical as f32, movement.y).clamp_length_max(1.0);
 
-        for (key, axis) in [
-            (options.key_back, &mut axis_input.z),
-            (options.key_left, &mut axis_input.x),
-            (options.key_down, &mut axis_input.y),
-        ] {
-            if key_input.pressed(key) {
-                *axis -= 1.0;
-            }
+        if actions.just_pressed(Action::ToggleWireframe) {
+            wireframe_config.global = !wireframe_config.global;
         }
 
-        if key_input.just_pressed(KeyCode::Grave) {
-            wireframe_config.global = !wireframe_config.global;
+        if actions.just_pressed(Action::ToggleFly) {
+            options.flying = !options.flying;
+            options.velocity = Vec3::ZERO;
         }
 
-        // Apply movement update
-        if axis_input != Vec3::ZERO {
-            let max_speed = if key_input.pressed(options.key_run) {
+        // Apply movement update, walking is handled by `PlayerPhysicsPlugin::walk`
+        if options.flying && axis_input != Vec3::ZERO {
+            let max_speed = if actions.pressed(Action::Run) {
                 options.run_speed
             } else {
                 options.walk_speed
             };
-            options.velocity = axis_input.normalize() * max_speed;
+            options.velocity = axis_input * max_speed;
         } else {
             let friction = options.friction.clamp(0.0, 1.0);
             options.velocity *= 1.0 - friction;
@@ -154,6 +144,7 @@ pub fn camera_controller(
 
 pub fn update_mouse(
     time: Res<Time>,
+    actions: Res<ActionState<Action>>,
     mut mouse_events: EventReader<MouseMotion>,
     mut query: Query<(&mut Transform, &mut CameraController), With<Camera>>,
 ) {
@@ -166,96 +157,181 @@ pub fn update_mouse(
         mouse_delta += mouse_event.delta;
     }
 
-    if mouse_delta != Vec2::ZERO {
-        options.pitch = (options.pitch - mouse_delta.y * 0.5 * options.sensitivity * dt)
-            .clamp(-PI / 2., PI / 2.);
-        options.yaw -= mouse_delta.x * options.sensitivity * dt;
+    // the mouse pitches at half its sensitivity
+    let mut turn = mouse_delta * Vec2::new(1.0, 0.5) * options.sensitivity * dt;
+
+    // sticks turn at a rate rather than by a distance, the same one along both axes
+    if let Some(look) = actions.axis_pair(Action::Look) {
+        turn += Vec2::new(look.x(), -look.y()) * options.stick_sensitivity * dt;
+    }
+
+    if turn != Vec2::ZERO {
+        options.pitch = (options.pitch - turn.y).clamp(-PI / 2., PI / 2.);
+        options.yaw -= turn.x;
         transform.rotation = Quat::from_euler(EulerRot::ZYX, 0.0, options.yaw, options.pitch);
     }
 }
 
+pub fn edit_blocks(
+    mut chunks: ResMut<Chunks>,
+    loaded_chunks: Res<LoadedChunks>,
+    actions: Res<ActionState<Action>>,
+    query: Query<(&Transform, &CameraController), With<Camera>>,
+) {
+    let (transform, options) = query.single();
+
+    let breaking = actions.just_pressed(Action::Break);
+    let placing = actions.just_pressed(Action::Place);
+
+    if !options.enabled || !(breaking || placing) {
+        return;
+    }
+
+    let Some(hit) = chunks.raycast(transform.translation, transform.forward(), options.reach)
+    else {
+        return;
+    };
+
+    if breaking {
+        chunks.edit_block_world(&loaded_chunks, hit.position, 0);
+    } else if hit.normal != IVec3::ZERO {
+        chunks.edit_block_world(
+            &loaded_chunks,
+            hit.position + hit.normal,
+            options.place_block,
+        );
+    }
+}
+
+/// Unloads every chunk, saving the player's edits, so the world is loaded again from scratch.
+#[allow(clippy::too_many_arguments)]
 pub fn reset_chunks(
+    mut commands: Commands,
     mut chunks: ResMut<Chunks>,
     mut loaded_chunks: ResMut<LoadedChunks>,
-    key_input: Res<Input<KeyCode>>,
+    mut jobs: ResMut<GenerationJobs>,
+    mut mesh_jobs: ResMut<MeshingJobs>,
+    world_storage: Res<WorldStorage>,
+    actions: Res<ActionState<Action>>,
+    mut query: Query<&mut CameraController, With<Camera>>,
 ) {
-    if key_input.pressed(KeyCode::R) {
-        loaded_chunks.reset();
-        chunks.reset();
+    if !actions.pressed(Action::ResetChunks) {
+        return;
+    }
+
+    loaded_chunks.reset();
+
+    let keys = loaded_chunks.pull_unload();
+
+    for key in &keys {
+        mesh_jobs.cancel(*key);
+    }
+
+    // saved right away, so the jobs generating the chunks again load the edits
+    for entity in chunks.unload(keys, &world_storage) {
+        commands.entity(entity).despawn();
     }
+
+    chunks.retain(|_| false);
+    chunks.retain_surfaces(|_| false);
+    jobs.retain(|_| false);
+    container::get_update_queue().retain(|_| false);
+
+    // makes `chunk_loading` request every chunk around the camera again
+    query.single_mut().last_chunk_pos = None;
 }
 
 pub fn chunk_loading(
     mut chunks: ResMut<Chunks>,
     mut loaded_chunks: ResMut<LoadedChunks>,
+    mut jobs: ResMut<GenerationJobs>,
+    mut ore_statistics: ResMut<OreStatistics>,
+    mut pending: ResMut<PendingWrites>,
     mut query: Query<(&mut Transform, &mut CameraController), With<Camera>>,
-    noise_data: Res<NoiseData>,
 ) {
     let (mut transform, mut camera) = query.single_mut();
     let transform = transform.as_mut();
     let camera = camera.as_mut();
-    let render_distance = 8f32;
 
-    let translation = transform.translation;
-    let (x, z) = (translation.x as i32, translation.z as i32);
+    let translation = transform.translation.floor();
+    let current = ChunkKey::from(Chunks::domain_of([
+        translation.x as i32,
+        translation.y as i32,
+        translation.z as i32,
+    ]));
 
-    let current = chunks.get_chunk_at([x, z]);
-    let world_pos = current.world_pos.clone();
+    container::get_update_queue().set_focus(transform.translation, transform.forward());
 
-    if camera.last_chunk_pos.is_none() {
-        camera.last_chunk_pos = Some((x, z));
-    }
+    if camera.last_chunk_pos != Some(current) {
+        let distance = IVec3::new(RENDER_DISTANCE, VERTICAL_RENDER_DISTANCE, RENDER_DISTANCE);
+        let (min, max) = (current - distance, current + distance);
+        let in_range = |key: ChunkKey| key.cmpge(min).all() && key.cmple(max).all();
 
-    let last_pos = camera.last_chunk_pos.unwrap();
-    let previous = chunks.get_chunk_at([last_pos.0, last_pos.1]);
+        for chunk in loaded_chunks.pull_loaded() {
+            if !in_range(chunk) {
+                loaded_chunks.queue_unload(chunk);
+            }
+        }
 
-    if world_pos.x != previous.world_pos.x || world_pos.y != previous.world_pos.y {
-        let min_x = ((x / X_SIZE as i32) as f32 - render_distance) as i32;
-        let max_x = ((x / X_SIZE as i32) as f32 + render_distance) as i32;
-        let min_z = ((z / Z_SIZE as i32) as f32 - render_distance) as i32;
-        let max_z = ((z / Z_SIZE as i32) as f32 + render_distance) as i32;
+        chunks.set_lod_center(current);
 
-        let noise_data = noise_data.as_ref().clone();
+        // chunks whose level of detail changed are re-meshed along with the neighbours
+        // their padding is shared with
+        for key in loaded_chunks.pull_loaded() {
+            let lod = chunks.lod_at(key);
+            let Some(chunk) = chunks.get_mut(key).filter(|chunk| chunk.lod != lod) else {
+                continue;
+            };
 
-        for chunk in loaded_chunks.pull_loaded() {
-            let [x, z] = Chunks::delinearize_domain(chunk);
+            chunk.lod = lod;
+            chunks.mark_dirty(key);
 
-            if (x < min_x || x > max_x) || (z < min_z || z > max_z) {
-                loaded_chunks.queue_unload(chunk);
+            for neighbour in container::neighbours(key) {
+                if loaded_chunks.is_chunk_id_loaded(&neighbour) {
+                    chunks.mark_dirty(neighbour);
+                }
             }
         }
 
-        std::thread::spawn(move || {
-            for x in min_x..max_x {
-                for z in min_z..max_z {
-                    let mut blocks = [0u8; ChunkShape::SIZE as usize];
-                    let linear = Chunks::linearize_domain([x, z]);
-
-                    let terrain = terrain::noise::generate_terrain_3d::<ChunkShape, NoiseShape>(
-                        &noise_data,
-                        rand::thread_rng().gen_range(0..=5000),
-                        DebugTerrainGenerator,
-                    );
-
-                    assert_eq!(terrain.len(), ChunkShape::SIZE as usize);
-
-                    for i in 0..ChunkShape::SIZE {
-                        let [inner_x, inner_y, inner_z] = ChunkShape::delinearize(i as u32);
-                        let linearized = ChunkShape::linearize([inner_x, inner_z, inner_y]);
-
-                        if (inner_x > 0 && inner_x < X_SIZE_U32)
-                            && (inner_y > 0 && inner_y < Y_SIZE_U32)
-                            && (inner_z > 0 && inner_z < Z_SIZE_U32)
-                        {
-                            blocks[linearized as usize] = terrain[i as usize];
-                        }
-                    }
+        // work for chunks that left the render distance is no longer needed
+        jobs.retain(in_range);
+        container::get_update_queue().retain(in_range);
+
+        // saved chunks wait in the container for their install, which was just dropped
+        chunks.retain(|key| {
+            in_range(key)
+                || loaded_chunks.is_chunk_id_loaded(&key)
+                || loaded_chunks.is_unloading(&key)
+        });
+        chunks.retain_surfaces(|column| in_range(IVec3::new(column.x, current.y, column.y)));
+        ore_statistics.retain(in_range);
+
+        // features reach at most into neighbouring chunks, so whatever anchored writes further
+        // out is unloaded as well and writes them again when it is generated
+        pending.retain(|key| key.cmpge(min - 1).all() && key.cmple(max + 1).all());
+
+        let queue = container::get_update_queue();
+        let mut missing = Vec::new();
+
+        for x in min.x..=max.x {
+            for z in min.z..=max.z {
+                for y in min.y..=max.y {
+                    let key = ChunkKey::new(x, y, z);
 
-                    container::get_update_queue().queue((linear, blocks));
+                    if !loaded_chunks.is_chunk_id_loaded(&key) && !queue.contains(key) {
+                        missing.push(key);
+                    }
                 }
             }
-        });
+        }
+
+        // generate chunks in the order the update queue will install them
+        missing.sort_by(|a, b| queue.priority(*a).total_cmp(&queue.priority(*b)));
+
+        for key in missing {
+            jobs.request(key);
+        }
 
-        camera.last_chunk_pos = Some((x, z));
+        camera.last_chunk_pos = Some(current);
     }
 }
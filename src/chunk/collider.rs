@@ -0,0 +1,119 @@
+use bevy::prelude::{Resource, Vec3};
+use bevy_rapier3d::prelude::Collider;
+
+use super::container::{ChunkKey, Chunks};
+
+/// Chunks from the camera's chunk along the furthest axis up to which terrain gets colliders.
+///
+/// Colliders are built from the chunk's mesh, so past the first level of detail distance they
+/// follow the coarser terrain.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhysicsRadius(pub i32);
+
+impl Default for PhysicsRadius {
+    fn default() -> Self {
+        Self(2)
+    }
+}
+
+/// Triangles of the faces of solid blocks in a chunk, relative to its minimum corner.
+#[derive(Clone, Debug, Default)]
+pub struct CollisionMesh {
+    pub vertices: Vec<Vec3>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl CollisionMesh {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Adds the triangles listed by `indices`, which index into `positions`.
+    pub fn extend(&mut self, positions: &[[f32; 3]], indices: &[u32]) {
+        let offset = self.vertices.len() as u32;
+
+        self.vertices
+            .extend(positions.iter().copied().map(Vec3::from_array));
+        self.indices.extend(
+            indices
+                .chunks_exact(3)
+                .map(|triangle| [0, 1, 2].map(|corner| triangle[corner] + offset)),
+        );
+    }
+
+    /// A static triangle mesh collider, or `None` if there is nothing to collide with.
+    pub fn into_collider(self) -> Option<Collider> {
+        (!self.is_empty()).then(|| Collider::trimesh(self.vertices, self.indices))
+    }
+}
+
+impl Chunks {
+    /// Whether the chunk at `key` is close enough to the camera to get a collider.
+    pub fn in_physics_radius(&self, key: ChunkKey, radius: PhysicsRadius) -> bool {
+        (key - self.lod_center()).abs().max_element() <= radius.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CollisionMesh, PhysicsRadius};
+    use crate::{
+        chunk::{
+            container::{ChunkKey, Chunks, DomainChunk},
+            meshing::MeshingMode,
+            storage::ChunkStorage,
+        },
+        material::Materials,
+    };
+
+    #[test]
+    pub fn collision_mesh_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+
+        chunks
+            .get_domain_at_mut([0, 0, 0])
+            .override_blocks(ChunkStorage::Uniform(2));
+
+        // a solid chunk collides with its six merged faces, in both meshing modes
+        for mode in [MeshingMode::Blocky, MeshingMode::Smooth] {
+            let collision = chunks
+                .padded_view(ChunkKey::ZERO)
+                .mesh(&materials, mode)
+                .collision;
+
+            assert!(!collision.is_empty());
+            assert!(collision
+                .indices
+                .iter()
+                .flatten()
+                .all(|index| (*index as usize) < collision.vertices.len()));
+        }
+
+        let blocky = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Blocky)
+            .collision;
+
+        assert_eq!(blocky.indices.len(), 6 * 2);
+        assert!(blocky.into_collider().is_some());
+
+        // water is drawn but can be walked through
+        chunks
+            .get_domain_at_mut([0, 0, 0])
+            .override_blocks(ChunkStorage::Uniform(3));
+
+        let water = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Blocky);
+
+        assert!(water.transparent.count_vertices() > 0);
+        assert!(water.collision.is_empty());
+        assert!(CollisionMesh::default().into_collider().is_none());
+
+        chunks.set_lod_center(ChunkKey::new(4, 0, 0));
+
+        assert!(chunks.in_physics_radius(ChunkKey::new(2, -2, 1), PhysicsRadius(2)));
+        assert!(!chunks.in_physics_radius(ChunkKey::new(1, 0, 0), PhysicsRadius(2)));
+    }
+}
@@ -2,12 +2,12 @@ use bevy::{prelude::Resource, utils::HashSet};
 
 use crate::chunk::Chunk;
 
-use super::{Chunks, DomainChunk};
+use super::ChunkKey;
 
 #[derive(Resource, Default)]
 pub struct LoadedChunks {
-    chunks: HashSet<i32>,
-    to_unload: HashSet<i32>,
+    chunks: HashSet<ChunkKey>,
+    to_unload: HashSet<ChunkKey>,
 }
 
 impl LoadedChunks {
@@ -15,27 +15,33 @@ impl LoadedChunks {
         self.to_unload.extend(self.chunks.drain());
     }
 
-    pub fn add_rendered_chunk(&mut self, chunk: i32) {
+    pub fn add_rendered_chunk(&mut self, chunk: ChunkKey) {
         self.chunks.insert(chunk);
     }
 
     pub fn is_chunk_loaded(&self, chunk: &Chunk) -> bool {
-        self.is_chunk_id_loaded(&(Chunks::linearize([chunk.world_pos.x, chunk.world_pos.y])))
+        self.is_chunk_id_loaded(&chunk.position)
     }
 
-    pub fn is_chunk_id_loaded(&self, index: &i32) -> bool {
-        self.chunks.contains(index)
+    pub fn is_chunk_id_loaded(&self, key: &ChunkKey) -> bool {
+        self.chunks.contains(key)
     }
 
-    pub fn queue_unload(&mut self, chunk: i32) {
+    pub fn queue_unload(&mut self, chunk: ChunkKey) {
+        self.chunks.remove(&chunk);
         self.to_unload.insert(chunk);
     }
 
-    pub fn pull_unload(&mut self) -> HashSet<i32> {
+    /// Whether the chunk at `key` left the loaded chunks and wasn't unloaded yet.
+    pub fn is_unloading(&self, key: &ChunkKey) -> bool {
+        self.to_unload.contains(key)
+    }
+
+    pub fn pull_unload(&mut self) -> HashSet<ChunkKey> {
         self.to_unload.drain().collect()
     }
 
-    pub fn pull_loaded(&self) -> HashSet<i32> {
+    pub fn pull_loaded(&self) -> HashSet<ChunkKey> {
         self.chunks.clone()
     }
 }
@@ -1,11 +1,13 @@
-use bevy::{prelude::Resource, utils::HashMap};
-use ndshape::ConstShape;
+use bevy::{
+    prelude::{IVec2, IVec3, Resource},
+    utils::{HashMap, HashSet},
+};
 use once_cell::sync::Lazy;
 use parking_lot::{RwLock, RwLockWriteGuard};
 
 use self::queue::ChunkUpdateQueue;
 
-use super::{Chunk, ChunkShape, X_SIZE, Z_SIZE};
+use super::{light::LightUpdates, Chunk, X_SIZE, Y_SIZE, Z_SIZE};
 
 pub mod loaded;
 pub mod queue;
@@ -17,104 +19,209 @@ pub fn get_update_queue<'a>() -> RwLockWriteGuard<'a, ChunkUpdateQueue> {
     CHUNK_UPDATE_QUEUE.write()
 }
 
+/// Position of a chunk in chunk coordinates, i.e. world block position divided by the chunk size.
+pub type ChunkKey = IVec3;
+
+/// Returns the keys of the 26 chunks sharing a face, edge or corner with the chunk at `key`.
+pub fn neighbours(key: ChunkKey) -> impl Iterator<Item = ChunkKey> {
+    (-1..=1)
+        .flat_map(|z| (-1..=1).flat_map(move |y| (-1..=1).map(move |x| IVec3::new(x, y, z))))
+        .filter(|offset| *offset != IVec3::ZERO)
+        .map(move |offset| key + offset)
+}
+
 pub trait DomainChunk<const N: usize> {
-    fn get_domain_at(&mut self, dimensions: [i32; N]) -> &Chunk;
-    fn get_domain_at_mut(&mut self, dimensions: [i32; N]) -> &mut Chunk;
-    fn get_chunk_at(&mut self, dimensions: [i32; N]) -> &Chunk;
-    fn get_chunk_at_mut(&mut self, dimensions: [i32; N]) -> &mut Chunk;
-    fn linearize_domain(dimensions: [i32; N]) -> i32;
-    fn delinearize_domain(id: i32) -> [i32; N];
-    fn linearize(dimensions: [i32; N]) -> i32;
-    fn delinearize(id: i32) -> [i32; N];
+    fn get_domain_at(&mut self, key: [i32; N]) -> &Chunk;
+    fn get_domain_at_mut(&mut self, key: [i32; N]) -> &mut Chunk;
+    fn get_chunk_at(&mut self, position: [i32; N]) -> &Chunk;
+    fn get_chunk_at_mut(&mut self, position: [i32; N]) -> &mut Chunk;
+    /// Returns the key of the chunk containing the world block `position`.
+    fn domain_of(position: [i32; N]) -> [i32; N];
+    /// Returns the world block position of the minimum corner of the chunk at `key`.
+    fn domain_origin(key: [i32; N]) -> [i32; N];
 }
 
 #[derive(Resource, Default, Clone)]
 pub struct Chunks {
-    chunks: HashMap<i32, Chunk>,
+    chunks: HashMap<ChunkKey, Chunk>,
+    dirty: HashSet<ChunkKey>,
+    lod_center: ChunkKey,
+    light_updates: LightUpdates,
+    /// Top of the generated ground in every block column of a chunk column, indexed
+    /// `z * X_SIZE + x`, skylight falls in from above it.
+    surfaces: HashMap<IVec2, Box<[i32]>>,
 }
 
 unsafe impl Send for Chunks {}
 unsafe impl Sync for Chunks {}
 
 impl Chunks {
-    pub fn reset(&mut self) {
-        for chunk in self.chunks.values_mut() {
-            chunk.override_blocks([0u8; ChunkShape::SIZE as usize]);
+    /// Drops the chunk at `key` and everything queued for it. Its entities are left for the
+    /// caller to despawn.
+    pub fn remove(&mut self, key: ChunkKey) -> Option<Chunk> {
+        self.dirty.remove(&key);
+        self.chunks.remove(&key)
+    }
+
+    /// Drops every chunk whose key doesn't match `keep`, see [`Chunks::remove`].
+    pub fn retain(&mut self, keep: impl Fn(ChunkKey) -> bool) {
+        self.chunks.retain(|key, _| keep(*key));
+        self.dirty.retain(|key| keep(*key));
+    }
+
+    pub fn get(&self, key: ChunkKey) -> Option<&Chunk> {
+        self.chunks.get(&key)
+    }
+
+    pub fn get_mut(&mut self, key: ChunkKey) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values()
+    }
+
+    /// Queues the chunk at `key` to be re-meshed.
+    pub fn mark_dirty(&mut self, key: ChunkKey) {
+        if let Some(chunk) = self.chunks.get_mut(&key) {
+            chunk.dirty = true;
+            chunk.revision += 1;
+            self.dirty.insert(key);
         }
     }
-}
 
-impl DomainChunk<2> for Chunks {
-    fn linearize_domain([x, z]: [i32; 2]) -> i32 {
-        Chunks::linearize([x * X_SIZE as i32, z * Z_SIZE as i32])
+    /// Queues the chunk at `key` to be re-meshed after the block at `local` changed,
+    /// along with every neighbour whose padded view contains that block.
+    pub fn mark_block_dirty(&mut self, key: ChunkKey, local: [u32; 3]) {
+        for dependent in Self::block_dependents(key, local) {
+            self.mark_dirty(dependent);
+        }
     }
 
-    fn delinearize_domain(id: i32) -> [i32; 2] {
-        let [x, z] = Chunks::delinearize(id);
+    /// Returns the key of the chunk at `key` and of every neighbour whose padded view contains
+    /// the block at `local`.
+    pub fn block_dependents(key: ChunkKey, local: [u32; 3]) -> impl Iterator<Item = ChunkKey> {
+        let [x, y, z] = [(0, X_SIZE), (1, Y_SIZE), (2, Z_SIZE)].map(|(axis, size)| {
+            if local[axis] == 0 {
+                -1..=0
+            } else if local[axis] as usize == size - 1 {
+                0..=1
+            } else {
+                0..=0
+            }
+        });
+
+        z.flat_map(move |offset_z| {
+            let x = x.clone();
+
+            y.clone().flat_map(move |offset_y| {
+                x.clone()
+                    .map(move |offset_x| key + IVec3::new(offset_x, offset_y, offset_z))
+            })
+        })
+    }
 
-        [x / X_SIZE as i32, z / Z_SIZE as i32]
+    /// Queues the chunk at `key` to be lit from scratch by [`Chunks::update_light`], after its
+    /// blocks were installed or its neighbours changed which of them are loaded.
+    pub fn queue_light(&mut self, key: ChunkKey) {
+        self.light_updates.queue_chunk(key);
     }
 
-    fn linearize([x, z]: [i32; 2]) -> i32 {
-        let (x_size, z_size) = (X_SIZE as i32, Z_SIZE as i32);
-        (x / x_size) * 1024 + (z / z_size)
+    /// Queues the light around the world block `position` to be updated by
+    /// [`Chunks::update_light`] after the block changed.
+    pub fn queue_block_light(&mut self, position: IVec3) {
+        self.light_updates.queue_block(position);
     }
 
-    fn delinearize(id: i32) -> [i32; 2] {
-        let x_size = X_SIZE as i32;
-        let z_size = Z_SIZE as i32;
-        [(id / 1024) * x_size, (id % 1024) * z_size]
+    /// Whether the chunk at `key` is still waiting to be lit, meshing it now would only have to
+    /// be redone once it is.
+    pub fn is_light_queued(&self, key: ChunkKey) -> bool {
+        self.light_updates.contains_chunk(key)
     }
 
-    fn get_chunk_at(&mut self, [x, z]: [i32; 2]) -> &Chunk {
-        let id = Self::linearize([x, z]);
+    pub fn has_light_updates(&self) -> bool {
+        !self.light_updates.is_empty()
+    }
 
-        if self.chunks.contains_key(&id) {
-            return self.chunks.get(&id).unwrap();
-        }
+    pub(super) fn take_light_updates(&mut self) -> LightUpdates {
+        std::mem::take(&mut self.light_updates)
+    }
 
-        let chunk = Chunk::new(x / X_SIZE as i32, z / Z_SIZE as i32);
+    /// Remembers the generated ground height of the chunk column at `column`, the first one
+    /// generated is kept since the others are the same.
+    pub fn insert_surface(&mut self, column: IVec2, surface: Box<[i32]>) {
+        assert_eq!(surface.len(), X_SIZE * Z_SIZE);
 
-        self.chunks.insert(id, chunk);
-        self.get_chunk_at([x, z])
+        self.surfaces.entry(column).or_insert(surface);
     }
 
-    fn get_chunk_at_mut(&mut self, [x, z]: [i32; 2]) -> &mut Chunk {
-        let id = Self::linearize([x, z]);
+    /// Top of the generated ground in the column of the world block `[x, z]`, if any chunk of
+    /// it was generated.
+    pub fn surface_at(&self, [x, z]: [i32; 2]) -> Option<i32> {
+        let column = IVec2::new(x.div_euclid(X_SIZE as i32), z.div_euclid(Z_SIZE as i32));
+        let [x, z] = [x.rem_euclid(X_SIZE as i32), z.rem_euclid(Z_SIZE as i32)];
 
-        if self.chunks.contains_key(&id) {
-            return self.chunks.get_mut(&id).unwrap();
-        }
+        self.surfaces
+            .get(&column)
+            .map(|surface| surface[z as usize * X_SIZE + x as usize])
+    }
 
-        let chunk = Chunk::new(x as i32, z as i32);
+    /// Drops the ground height of every chunk column that doesn't match `keep`.
+    pub fn retain_surfaces(&mut self, keep: impl Fn(IVec2) -> bool) {
+        self.surfaces.retain(|column, _| keep(*column));
+    }
 
-        self.chunks.insert(id, chunk);
-        self.get_chunk_at_mut([x, z])
+    /// Moves the chunk levels of detail are measured from, usually the one the camera is in.
+    pub fn set_lod_center(&mut self, key: ChunkKey) {
+        self.lod_center = key;
     }
 
-    fn get_domain_at(&mut self, [x, z]: [i32; 2]) -> &Chunk {
-        let id = ((x & 1023) << 10) | (z & 1023);
+    pub fn lod_center(&self) -> ChunkKey {
+        self.lod_center
+    }
 
-        if self.chunks.contains_key(&id) {
-            return self.chunks.get(&id).unwrap();
-        }
+    pub fn has_dirty(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    pub fn pull_dirty(&mut self) -> HashSet<ChunkKey> {
+        self.dirty.drain().collect()
+    }
 
-        let chunk = Chunk::new(x as i32, z as i32);
+    /// Bytes used by the block storage of every chunk in the container.
+    pub fn memory_usage(&self) -> usize {
+        self.chunks.values().map(Chunk::memory_usage).sum()
+    }
+}
 
-        self.chunks.insert(id, chunk);
-        self.get_domain_at([x, z])
+impl DomainChunk<3> for Chunks {
+    fn domain_of([x, y, z]: [i32; 3]) -> [i32; 3] {
+        [
+            x.div_euclid(X_SIZE as i32),
+            y.div_euclid(Y_SIZE as i32),
+            z.div_euclid(Z_SIZE as i32),
+        ]
     }
 
-    fn get_domain_at_mut(&mut self, [x, z]: [i32; 2]) -> &mut Chunk {
-        let id = ((x & 1023) << 10) | (z & 1023);
+    fn domain_origin([x, y, z]: [i32; 3]) -> [i32; 3] {
+        [x * X_SIZE as i32, y * Y_SIZE as i32, z * Z_SIZE as i32]
+    }
 
-        if self.chunks.contains_key(&id) {
-            return self.chunks.get_mut(&id).unwrap();
-        }
+    fn get_chunk_at(&mut self, position: [i32; 3]) -> &Chunk {
+        self.get_domain_at(Self::domain_of(position))
+    }
+
+    fn get_chunk_at_mut(&mut self, position: [i32; 3]) -> &mut Chunk {
+        self.get_domain_at_mut(Self::domain_of(position))
+    }
+
+    fn get_domain_at(&mut self, key: [i32; 3]) -> &Chunk {
+        self.get_domain_at_mut(key)
+    }
 
-        let chunk = Chunk::new(x as i32, z as i32);
+    fn get_domain_at_mut(&mut self, key: [i32; 3]) -> &mut Chunk {
+        let key = ChunkKey::from(key);
 
-        self.chunks.insert(id, chunk);
-        self.get_domain_at_mut([x, z])
+        self.chunks.entry(key).or_insert_with(|| Chunk::new(key))
     }
 }
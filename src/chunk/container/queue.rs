@@ -1,35 +1,218 @@
-use std::ops::RangeBounds;
+use std::{cmp::Ordering, collections::BinaryHeap};
 
-use ndshape::ConstShape;
+use bevy::{prelude::Vec3, utils::HashMap};
 
-use crate::chunk::ChunkShape;
+use crate::chunk::{density::DensityStorage, storage::ChunkStorage, X_SIZE, Y_SIZE, Z_SIZE};
 
-type ChunkQueueData = (i32, [u8; ChunkShape::SIZE as usize]);
+use super::ChunkKey;
 
+type ChunkQueueData = (ChunkKey, ChunkStorage, DensityStorage);
+
+/// Chunks waiting to be installed, pulled nearest to the camera first.
+///
+/// Chunks behind the camera count as up to twice as far away as ones straight ahead of it.
 #[derive(Debug, Default)]
 pub struct ChunkUpdateQueue {
-    chunks: Vec<ChunkQueueData>,
+    chunks: BinaryHeap<QueuedChunk>,
+    /// How often every key is in `chunks`, so lookups don't have to walk the heap.
+    keys: HashMap<ChunkKey, usize>,
+    origin: Vec3,
+    direction: Vec3,
+}
+
+#[derive(Debug)]
+struct QueuedChunk {
+    priority: f32,
+    data: ChunkQueueData,
+}
+
+impl PartialEq for QueuedChunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedChunk {}
+
+impl PartialOrd for QueuedChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedChunk {
+    // reversed so the heap pops the lowest priority value first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
 }
 
 impl ChunkUpdateQueue {
     pub fn queue(&mut self, chunk: ChunkQueueData) {
-        self.chunks.push(chunk);
+        let priority = self.priority(chunk.0);
+
+        *self.keys.entry(chunk.0).or_default() += 1;
+
+        self.chunks.push(QueuedChunk {
+            priority,
+            data: chunk,
+        });
+    }
+
+    /// Removes and returns the queued chunk with the highest priority.
+    pub fn pop(&mut self) -> Option<ChunkQueueData> {
+        let chunk = self.chunks.pop()?;
+
+        self.forget(chunk.data.0);
+        Some(chunk.data)
+    }
+
+    fn forget(&mut self, key: ChunkKey) {
+        if let Some(count) = self.keys.get_mut(&key) {
+            *count -= 1;
+
+            if *count == 0 {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    /// Moves the point chunks are prioritised around, re-ordering everything already queued.
+    pub fn set_focus(&mut self, origin: Vec3, direction: Vec3) {
+        let direction = direction.normalize_or_zero();
+
+        if origin == self.origin && direction == self.direction {
+            return;
+        }
+
+        self.origin = origin;
+        self.direction = direction;
+
+        let mut chunks = std::mem::take(&mut self.chunks).into_vec();
+
+        for chunk in &mut chunks {
+            chunk.priority = self.priority(chunk.data.0);
+        }
+
+        self.chunks = chunks.into();
+    }
+
+    /// Distance from the focus to the centre of the chunk at `key`, weighted by view direction.
+    /// Lower values are pulled first.
+    pub fn priority(&self, key: ChunkKey) -> f32 {
+        let size = Vec3::new(X_SIZE as f32, Y_SIZE as f32, Z_SIZE as f32);
+        let offset = (key.as_vec3() + 0.5) * size - self.origin;
+        let facing = offset.normalize_or_zero().dot(self.direction);
+
+        offset.length() * (1.5 - 0.5 * facing)
     }
 
     pub fn len(&self) -> usize {
         self.chunks.len()
     }
 
-    pub fn pull<T: RangeBounds<usize>>(&mut self, range: T) -> Vec<ChunkQueueData> {
-        return if range.contains(&self.chunks.len()) {
-            self.chunks.drain(..)
-        } else {
-            self.chunks.drain(range)
-        }
-        .collect();
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn contains(&self, key: ChunkKey) -> bool {
+        self.keys.contains_key(&key)
+    }
+
+    /// Drops every queued chunk whose key doesn't match `keep`.
+    pub fn retain(&mut self, keep: impl Fn(ChunkKey) -> bool) {
+        self.chunks.retain(|chunk| keep(chunk.data.0));
+        self.keys.retain(|key, _| keep(*key));
     }
 
     pub fn has_queue(&self) -> bool {
         !self.chunks.is_empty()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::{IVec3, Vec3};
+
+    use super::ChunkUpdateQueue;
+    use crate::chunk::{density::DensityStorage, storage::ChunkStorage};
+
+    #[test]
+    pub fn nearest_first_test() {
+        let mut queue = ChunkUpdateQueue::default();
+
+        for x in [5, -3, 1, 0, 8] {
+            queue.queue((
+                IVec3::new(x, 0, 0),
+                ChunkStorage::Uniform(1),
+                DensityStorage::default(),
+            ));
+        }
+
+        let order = std::iter::from_fn(|| queue.pop())
+            .map(|(key, _, _)| key.x)
+            .collect::<Vec<_>>();
+
+        assert_eq!(order, [0, 1, -3, 5, 8]);
+    }
+
+    #[test]
+    pub fn refocus_test() {
+        let mut queue = ChunkUpdateQueue::default();
+
+        queue.set_focus(Vec3::ZERO, Vec3::X);
+
+        for x in [-3, 2, 6] {
+            queue.queue((
+                IVec3::new(x, 0, 0),
+                ChunkStorage::Uniform(1),
+                DensityStorage::default(),
+            ));
+        }
+
+        // the chunk ahead of the camera beats the equally distant one behind it
+        assert_eq!(queue.pop().unwrap().0.x, 2);
+
+        queue.queue((
+            IVec3::new(2, 0, 0),
+            ChunkStorage::Uniform(1),
+            DensityStorage::default(),
+        ));
+        queue.set_focus(Vec3::new(200.0, 0.0, 0.0), Vec3::NEG_X);
+
+        assert_eq!(queue.pop().unwrap().0.x, 6);
+        assert_eq!(queue.pop().unwrap().0.x, 2);
+        assert_eq!(queue.pop().unwrap().0.x, -3);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    pub fn contains_test() {
+        let mut queue = ChunkUpdateQueue::default();
+        let chunk = |x| {
+            (
+                IVec3::new(x, 0, 0),
+                ChunkStorage::Uniform(1),
+                DensityStorage::default(),
+            )
+        };
+
+        for x in [1, 4, 4, 7] {
+            queue.queue(chunk(x));
+        }
+
+        assert!(queue.contains(IVec3::new(4, 0, 0)));
+        assert!(!queue.contains(IVec3::new(2, 0, 0)));
+
+        // a key queued twice stays queued until both are pulled
+        queue.pop();
+        queue.pop();
+        assert!(queue.contains(IVec3::new(4, 0, 0)));
+        queue.pop();
+        assert!(!queue.contains(IVec3::new(4, 0, 0)));
+
+        queue.retain(|key| key.x != 7);
+        assert!(!queue.contains(IVec3::new(7, 0, 0)));
+        assert!(queue.is_empty());
+    }
+}
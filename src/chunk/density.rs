@@ -0,0 +1,56 @@
+use ndshape::ConstShape;
+
+use super::{Chunk, ChunkShape};
+
+/// Steps a block is split into when storing densities, distances past a whole block are clamped.
+const DENSITY_STEPS: f32 = i8::MAX as f32;
+
+/// Signed distance of every block of a chunk to the generated ground, negative inside it.
+///
+/// Only the block around the surface matters for meshing, so distances are clamped to a single
+/// block and stored in a byte each.
+#[derive(Clone, Debug, Default)]
+pub enum DensityStorage {
+    /// Nothing got generated, like for chunks made by edits, only the blocks themselves count.
+    #[default]
+    Blocks,
+    Uniform(i8),
+    Full(Box<[i8]>),
+}
+
+impl DensityStorage {
+    pub fn from_densities(densities: &[f32]) -> Self {
+        assert_eq!(densities.len(), ChunkShape::SIZE as usize);
+
+        let steps = densities
+            .iter()
+            .map(|density| (density.clamp(-1.0, 1.0) * DENSITY_STEPS).round() as i8)
+            .collect::<Box<[_]>>();
+
+        match steps.iter().all(|step| *step == steps[0]) {
+            true => Self::Uniform(steps[0]),
+            false => Self::Full(steps),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<f32> {
+        let step = match self {
+            Self::Blocks => return None,
+            Self::Uniform(step) => *step,
+            Self::Full(steps) => steps[index],
+        };
+
+        Some(step as f32 / DENSITY_STEPS)
+    }
+}
+
+impl Chunk {
+    /// Generated density of the block at `position`, see [`DensityStorage`].
+    pub fn get_density(&self, position: [u32; 3]) -> Option<f32> {
+        self.density.get(ChunkShape::linearize(position) as usize)
+    }
+
+    pub fn override_density(&mut self, density: DensityStorage) {
+        self.density = density;
+    }
+}
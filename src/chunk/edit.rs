@@ -0,0 +1,116 @@
+use bevy::prelude::IVec3;
+
+use super::container::{loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk};
+
+impl Chunks {
+    /// Splits a world block position into the key of the chunk containing it
+    /// and the position of the block inside that chunk.
+    pub fn split_world_position(position: IVec3) -> (ChunkKey, [u32; 3]) {
+        let key = ChunkKey::from(Self::domain_of(position.to_array()));
+        let local = position - IVec3::from(Self::domain_origin(key.to_array()));
+
+        (key, local.as_uvec3().to_array())
+    }
+
+    /// Returns the block at the world block `position`, or air if its chunk doesn't exist.
+    pub fn get_block_world(&self, position: IVec3) -> u8 {
+        let (key, local) = Self::split_world_position(position);
+
+        self.get(key)
+            .map(|chunk| chunk.get_block(local))
+            .unwrap_or(0)
+    }
+
+    /// Places `id` at the world block `position` and queues every chunk whose mesh
+    /// depends on that block to be re-meshed, and the light around it to be updated.
+    pub fn set_block_world(&mut self, position: IVec3, id: u8) {
+        self.write_block_world(position, id, true);
+    }
+
+    /// Like [`Chunks::set_block_world`], but refuses to touch chunks that aren't in `loaded`
+    /// yet and returns whether the block was placed.
+    ///
+    /// A chunk still being generated would otherwise count as edited, keep only the edit once
+    /// it is installed and get saved without its terrain.
+    pub fn edit_block_world(&mut self, loaded: &LoadedChunks, position: IVec3, id: u8) -> bool {
+        let (key, _) = Self::split_world_position(position);
+
+        if !loaded.is_chunk_id_loaded(&key) {
+            return false;
+        }
+
+        self.set_block_world(position, id);
+        true
+    }
+
+    /// Like [`Chunks::set_block_world`], but for blocks placed by world generation, which
+    /// don't count as edits and aren't saved.
+    pub fn generate_block_world(&mut self, position: IVec3, id: u8) {
+        self.write_block_world(position, id, false);
+    }
+
+    fn write_block_world(&mut self, position: IVec3, id: u8, edit: bool) {
+        let (key, local) = Self::split_world_position(position);
+        let chunk = self.get_domain_at_mut(key.to_array());
+
+        if chunk.get_block(local) == id {
+            return;
+        }
+
+        chunk.set_block(local, id);
+        chunk.edited |= edit;
+        chunk.unsaved |= edit;
+
+        self.mark_block_dirty(key, local);
+        self.queue_block_light(position);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::IVec3;
+
+    use crate::chunk::container::{loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk};
+
+    #[test]
+    pub fn set_block_world_test() {
+        let mut chunks = Chunks::default();
+
+        for key in [[-1, -1, 0], [-2, -1, 0], [-1, 0, 0], [-2, 0, 0], [0, -1, 0]] {
+            chunks.get_domain_at_mut(key);
+        }
+
+        chunks.pull_dirty();
+        chunks.set_block_world(IVec3::new(-32, -1, 5), 2);
+
+        assert_eq!(chunks.get_block_world(IVec3::new(-32, -1, 5)), 2);
+        assert_eq!(chunks.get_block_world(IVec3::new(-31, -1, 5)), 0);
+
+        let chunk = chunks.get(ChunkKey::new(-1, -1, 0)).unwrap();
+
+        assert_eq!(chunk.get_block([0, 31, 5]), 2);
+        assert!(chunk.edited && chunk.unsaved);
+
+        // the block sits on the low x and high y border of its chunk
+        let dirty = chunks.pull_dirty();
+
+        assert_eq!(dirty.len(), 4);
+        assert!(!dirty.contains(&ChunkKey::new(0, -1, 0)));
+
+        // writing the same block again doesn't re-mesh anything
+        chunks.set_block_world(IVec3::new(-32, -1, 5), 2);
+        assert!(!chunks.has_dirty());
+
+        // edits only reach chunks that finished generating
+        let mut loaded = LoadedChunks::default();
+
+        loaded.add_rendered_chunk(ChunkKey::new(-1, -1, 0));
+
+        assert!(chunks.edit_block_world(&loaded, IVec3::new(-32, -2, 5), 1));
+        assert!(!chunks.edit_block_world(&loaded, IVec3::new(0, -1, 5), 1));
+        assert!(!chunks.get(ChunkKey::new(0, -1, 0)).unwrap().edited);
+        assert!(chunks.get(ChunkKey::new(5, 0, 0)).is_none());
+        assert!(!chunks.edit_block_world(&loaded, IVec3::new(160, 0, 0), 1));
+        assert!(chunks.get(ChunkKey::new(5, 0, 0)).is_none());
+    }
+}
@@ -0,0 +1,450 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use bevy::{
+    log::error,
+    prelude::{IVec2, IVec3, Local, Plugin, Res, ResMut, Resource},
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use futures_lite::future;
+
+use crate::{
+    material::Materials,
+    terrain::{
+        self,
+        biome::Biomes,
+        feature::Features,
+        noise::{DensitySampler, NoiseData, WorldSeed},
+        ore::{OrePass, OreStatistics},
+        BiomeTerrainGenerator,
+    },
+    world::WorldStorage,
+};
+
+use super::{
+    container::{self, loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk},
+    density::DensityStorage,
+    pending::PendingWrites,
+    storage::ChunkStorage,
+    ChunkShape, X_SIZE, Z_SIZE,
+};
+
+/// Output of a generation job.
+pub struct GeneratedChunk {
+    pub blocks: ChunkStorage,
+    /// Distance of every block to the generated ground, also for saved chunks, where only
+    /// the blocks the player didn't change still agree with it.
+    pub density: DensityStorage,
+    /// Feature blocks that reach out of the chunk, by world position.
+    pub spill: Vec<(IVec3, u8)>,
+    /// Whether `blocks` came from the world's save, which only holds chunks the player edited.
+    pub saved: bool,
+    /// Top of the generated ground in every block column, see [`Chunks::insert_surface`].
+    pub surface: Box<[i32]>,
+}
+
+/// Everything a generation job needs from the world's resources, cloned into its task.
+#[derive(Clone)]
+pub struct ChunkGenerator {
+    pub noise_data: NoiseData,
+    pub seed: u32,
+    pub biomes: Biomes,
+    pub terrain: BiomeTerrainGenerator,
+    pub ores: OrePass,
+    pub features: Features,
+}
+
+impl ChunkGenerator {
+    /// Generates the chunk at `key`, or takes its `saved` blocks instead.
+    ///
+    /// Either way, its features are placed again for what they spill into neighbours, since
+    /// only edited chunks get saved and the neighbours regenerate without those blocks.
+    pub fn generate(&self, key: ChunkKey, saved: Option<ChunkStorage>) -> GeneratedChunk {
+        let origin = Chunks::domain_origin(key.to_array());
+        let sampler = self.biomes.sampler(&self.noise_data, self.seed);
+        let density = DensitySampler::new(&self.noise_data, self.seed);
+
+        let (mut terrain, densities) = terrain::noise::generate_terrain_density_3d::<ChunkShape>(
+            &self.noise_data,
+            self.seed,
+            &self.biomes,
+            origin,
+            self.terrain,
+        );
+        let densities = DensityStorage::from_densities(&densities);
+        let surface = (0..Z_SIZE as i32)
+            .flat_map(|z| (0..X_SIZE as i32).map(move |x| [x, z]))
+            .map(|[x, z]| {
+                let column = sampler.sample(origin[0] + x, origin[2] + z);
+
+                column.height.floor() as i32
+            })
+            .collect();
+
+        let Some(blocks) = saved else {
+            self.ores
+                .apply::<ChunkShape>(self.seed, origin, &mut terrain);
+
+            let spill = self.features.place::<ChunkShape>(
+                &sampler,
+                &density,
+                self.seed,
+                origin,
+                &mut terrain,
+            );
+
+            return GeneratedChunk {
+                blocks: ChunkStorage::from_blocks(&terrain),
+                density: densities,
+                spill,
+                saved: false,
+                surface,
+            };
+        };
+
+        // placement doesn't look at the blocks, the saved chunk already holds its own part
+        let spill =
+            self.features
+                .place::<ChunkShape>(&sampler, &density, self.seed, origin, &mut terrain);
+
+        GeneratedChunk {
+            blocks,
+            density: densities,
+            spill,
+            saved: true,
+            surface,
+        }
+    }
+}
+
+/// Buffers the feature blocks a chunk spilled for the chunks they belong to, and writes them
+/// straight into the ones that are already installed, unless the player edited those.
+fn write_spill(
+    chunks: &mut Chunks,
+    loaded_chunks: &LoadedChunks,
+    pending: &mut PendingWrites,
+    spill: Vec<(IVec3, u8)>,
+) {
+    for (position, block) in spill {
+        let target = pending.push(position, block);
+        let edited = chunks.get(target).is_some_and(|chunk| chunk.edited);
+
+        // chunks that are already in place don't get installed again, the rest pick the write
+        // up when they are installed
+        if loaded_chunks.is_chunk_id_loaded(&target) && !edited {
+            chunks.generate_block_world(position, block);
+        }
+    }
+}
+
+/// Schedules chunk generation on the [`AsyncComputeTaskPool`], running at most `limit` jobs at once.
+///
+/// A chunk key is only ever queued or running once, and dropping a job through
+/// [`GenerationJobs::retain`] cancels its task.
+#[derive(Resource)]
+pub struct GenerationJobs {
+    limit: usize,
+    pending: VecDeque<ChunkKey>,
+    running: HashMap<ChunkKey, Task<GeneratedChunk>>,
+}
+
+impl Default for GenerationJobs {
+    fn default() -> Self {
+        let limit = std::thread::available_parallelism().map_or(4, |threads| threads.get());
+
+        Self::new(limit)
+    }
+}
+
+impl GenerationJobs {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            pending: VecDeque::new(),
+            running: HashMap::new(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Queues the chunk at `key` to be generated, returns `false` if it already is.
+    pub fn request(&mut self, key: ChunkKey) -> bool {
+        if self.contains(key) {
+            return false;
+        }
+
+        self.pending.push_back(key);
+        true
+    }
+
+    pub fn contains(&self, key: ChunkKey) -> bool {
+        self.running.contains_key(&key) || self.pending.contains(&key)
+    }
+
+    /// Cancels every queued or running job whose key doesn't match `keep`.
+    pub fn retain(&mut self, keep: impl Fn(ChunkKey) -> bool) {
+        self.pending.retain(|key| keep(*key));
+        self.running.retain(|key, _| keep(*key));
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len() + self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.running.is_empty()
+    }
+
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// Removes and returns the jobs that finished since the last call.
+    fn pull_finished(&mut self) -> Vec<(ChunkKey, GeneratedChunk)> {
+        let finished = self
+            .running
+            .iter_mut()
+            .filter_map(|(key, task)| {
+                future::block_on(future::poll_once(task)).map(|chunk| (*key, chunk))
+            })
+            .collect::<Vec<_>>();
+
+        for (key, _) in &finished {
+            self.running.remove(key);
+        }
+
+        finished
+    }
+
+    /// Starts queued jobs until `limit` are running, each one calling `generate` with its key.
+    fn spawn_pending(&mut self, generate: impl Fn(ChunkKey) -> Task<GeneratedChunk>) {
+        while self.running.len() < self.limit {
+            let Some(key) = self.pending.pop_front() else {
+                break;
+            };
+
+            self.running.insert(key, generate(key));
+        }
+    }
+}
+
+pub struct GenerationPlugin;
+
+impl GenerationPlugin {
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_jobs(
+        mut jobs: ResMut<GenerationJobs>,
+        mut chunks: ResMut<Chunks>,
+        mut loaded_chunks: ResMut<LoadedChunks>,
+        mut pending: ResMut<PendingWrites>,
+        mut ore_statistics: ResMut<OreStatistics>,
+        noise_data: Res<NoiseData>,
+        world_seed: Res<WorldSeed>,
+        biomes: Res<Biomes>,
+        features: Res<Features>,
+        world_storage: Res<WorldStorage>,
+        materials: Res<Materials>,
+        mut generator: Local<Option<Arc<ChunkGenerator>>>,
+    ) {
+        // checked before returning, changes are only reported to the next run
+        if materials.is_changed()
+            || noise_data.is_changed()
+            || world_seed.is_changed()
+            || biomes.is_changed()
+            || features.is_changed()
+        {
+            *generator = None;
+        }
+
+        if jobs.is_empty() {
+            return;
+        }
+
+        let generator = generator
+            .get_or_insert_with(|| {
+                Arc::new(ChunkGenerator {
+                    noise_data: noise_data.as_ref().clone(),
+                    seed: world_seed.0,
+                    biomes: biomes.as_ref().clone(),
+                    terrain: BiomeTerrainGenerator::from_materials(&materials),
+                    ores: OrePass::from_materials(&materials),
+                    features: features.as_ref().clone(),
+                })
+            })
+            .clone();
+
+        for (key, generated) in jobs.pull_finished() {
+            let GeneratedChunk {
+                blocks,
+                density,
+                spill,
+                saved,
+                surface,
+            } = generated;
+
+            chunks.insert_surface(IVec2::new(key.x, key.z), surface);
+            ore_statistics.record(key, generator.ores.count(&blocks));
+            write_spill(&mut chunks, &loaded_chunks, &mut pending, spill);
+
+            // saved chunks count as edited again, so features of their neighbours don't grow
+            // back over the player's changes, and installing them keeps the blocks set here
+            if saved {
+                let chunk = chunks.get_domain_at_mut(key.to_array());
+
+                chunk.override_blocks(blocks.clone());
+                chunk.edited = true;
+            }
+
+            // chunks made out of only air have nothing to render, but count as loaded so they
+            // aren't generated again
+            if matches!(blocks, ChunkStorage::Uniform(0)) && !pending.contains(key) {
+                loaded_chunks.add_rendered_chunk(key);
+                // its neighbours are lit through it where it lies above the ground
+                chunks.queue_light(key);
+                continue;
+            }
+
+            container::get_update_queue().queue((key, blocks, density));
+        }
+
+        let pool = AsyncComputeTaskPool::get();
+
+        jobs.spawn_pending(|key| {
+            let world_storage = world_storage.clone();
+            let generator = generator.clone();
+
+            pool.spawn(async move {
+                let saved = world_storage.load_chunk(key).unwrap_or_else(|error| {
+                    error!("failed to load chunk {key}: {error}");
+                    None
+                });
+
+                generator.generate(key, saved)
+            })
+        });
+    }
+}
+
+impl Plugin for GenerationPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<GenerationJobs>()
+            .init_resource::<OreStatistics>()
+            .init_resource::<PendingWrites>()
+            .add_system(Self::process_jobs);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{prelude::IVec3, utils::HashMap};
+
+    use super::{write_spill, ChunkGenerator, GenerationJobs};
+    use crate::{
+        chunk::{
+            container::{loaded::LoadedChunks, Chunks, DomainChunk},
+            pending::PendingWrites,
+        },
+        material::Materials,
+        terrain::{
+            biome::Biomes, feature::Features, noise::NoiseData, ore::OrePass, BiomeTerrainGenerator,
+        },
+    };
+
+    #[test]
+    pub fn request_dedup_test() {
+        let mut jobs = GenerationJobs::new(0);
+
+        assert!(jobs.request(IVec3::ZERO));
+        assert!(jobs.request(IVec3::X));
+        assert!(!jobs.request(IVec3::ZERO));
+        assert_eq!(jobs.len(), 2);
+
+        // nothing runs past the concurrency limit
+        jobs.spawn_pending(|_| unreachable!());
+        assert_eq!(jobs.running(), 0);
+    }
+
+    #[test]
+    pub fn cancellation_test() {
+        let mut jobs = GenerationJobs::new(0);
+
+        for x in -4..=4 {
+            jobs.request(IVec3::new(x, 0, 0));
+        }
+
+        jobs.retain(|key| key.x.abs() <= 2);
+
+        assert_eq!(jobs.len(), 5);
+        assert!(!jobs.contains(IVec3::new(3, 0, 0)));
+        assert!(jobs.request(IVec3::new(3, 0, 0)));
+    }
+
+    #[test]
+    pub fn saved_chunk_spill_test() {
+        let materials = Materials::default();
+        let generator = ChunkGenerator {
+            noise_data: NoiseData::new(),
+            seed: 21,
+            biomes: Biomes::default(),
+            terrain: BiomeTerrainGenerator::default(),
+            ores: OrePass::from_materials(&materials),
+            features: Features::embedded(&materials).unwrap(),
+        };
+
+        // find a chunk with features reaching into its neighbours
+        let (key, generated) = (-2..=2)
+            .flat_map(|z| (-1..=1).flat_map(move |y| (-2..=2).map(move |x| IVec3::new(x, y, z))))
+            .map(|key| (key, generator.generate(key, None)))
+            .find(|(_, generated)| !generated.spill.is_empty())
+            .expect("a feature crosses a chunk border");
+
+        assert!(!generated.saved);
+
+        // the player edits the chunk, it gets saved on unload and later loaded from the save
+        let mut edited = generated.blocks.clone();
+
+        edited.set(0, 1);
+
+        let reloaded = generator.generate(key, Some(edited.clone()));
+
+        assert!(reloaded.saved);
+        assert_eq!(reloaded.blocks.to_vec(), edited.to_vec());
+        assert_eq!(reloaded.spill, generated.spill);
+
+        // where features overlap, the last write to a position is the one that sticks
+        let (position, block) = reloaded
+            .spill
+            .iter()
+            .copied()
+            .collect::<HashMap<_, _>>()
+            .into_iter()
+            .find(|(_, block)| *block != 0)
+            .unwrap();
+        let (target, _) = Chunks::split_world_position(position);
+
+        let mut chunks = Chunks::default();
+        let mut loaded = LoadedChunks::default();
+        let mut pending = PendingWrites::default();
+
+        // a neighbour the player changed keeps its blocks
+        loaded.add_rendered_chunk(target);
+        chunks.get_domain_at_mut(target.to_array()).edited = true;
+        write_spill(&mut chunks, &loaded, &mut pending, reloaded.spill.clone());
+
+        assert_eq!(chunks.get_block_world(position), 0);
+        assert!(pending.contains(target));
+
+        // an untouched one gets the feature back
+        chunks.get_domain_at_mut(target.to_array()).edited = false;
+        write_spill(&mut chunks, &loaded, &mut pending, reloaded.spill);
+
+        assert_eq!(chunks.get_block_world(position), block);
+        assert!(!chunks.get(target).unwrap().edited);
+    }
+}
@@ -0,0 +1,928 @@
+use std::{cell::Cell, collections::VecDeque, time::Instant};
+
+use bevy::{prelude::IVec3, utils::HashSet};
+use block_mesh::VoxelVisibility;
+use ndshape::ConstShape;
+
+use crate::material::Materials;
+
+use super::{
+    container::{loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk},
+    Chunk, ChunkShape, X_SIZE, Y_SIZE, Z_SIZE,
+};
+
+/// Light level of open sky and of the brightest light sources.
+pub const MAX_LIGHT: u8 = 15;
+
+/// Faint light left in complete darkness, so caves aren't drawn pitch black.
+const MIN_BRIGHTNESS: f32 = 0.05;
+
+/// The six directions light spreads in.
+const DIRECTIONS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightKind {
+    /// Light falling in from the sky, it travels straight down without getting weaker.
+    Sky,
+    /// The red part of the light given off by blocks, every colour spreads on its own.
+    Red,
+    Green,
+    Blue,
+}
+
+impl LightKind {
+    pub const ALL: [LightKind; 4] = [
+        LightKind::Sky,
+        LightKind::Red,
+        LightKind::Green,
+        LightKind::Blue,
+    ];
+
+    /// Bit offset of the level of this kind inside a [`Light`].
+    fn shift(self) -> u16 {
+        match self {
+            LightKind::Sky => 12,
+            LightKind::Red => 8,
+            LightKind::Green => 4,
+            LightKind::Blue => 0,
+        }
+    }
+}
+
+/// Sky light and the red, green and blue block light of a block, packed four bits each.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Light(u16);
+
+impl Light {
+    /// No light at all.
+    pub const DARK: Self = Self(0);
+    /// Open sky, what blocks of a chunk that wasn't lit yet are assumed to get.
+    pub const FULL: Self = Self((MAX_LIGHT as u16) << 12);
+
+    pub fn new(sky: u8, [red, green, blue]: [u8; 3]) -> Self {
+        Self::DARK
+            .with(LightKind::Sky, sky)
+            .with(LightKind::Red, red)
+            .with(LightKind::Green, green)
+            .with(LightKind::Blue, blue)
+    }
+
+    pub fn sky(self) -> u8 {
+        self.get(LightKind::Sky)
+    }
+
+    /// Red, green and blue block light.
+    pub fn block(self) -> [u8; 3] {
+        [LightKind::Red, LightKind::Green, LightKind::Blue].map(|kind| self.get(kind))
+    }
+
+    pub fn get(self, kind: LightKind) -> u8 {
+        (self.0 >> kind.shift() & 0xf) as u8
+    }
+
+    pub fn with(self, kind: LightKind, level: u8) -> Self {
+        let shift = kind.shift();
+
+        Self(self.0 & !(0xf << shift) | (level.min(MAX_LIGHT) as u16) << shift)
+    }
+
+    /// The stronger level of every kind.
+    pub fn max(self, other: Light) -> Self {
+        LightKind::ALL.into_iter().fold(self, |light, kind| {
+            light.with(kind, self.get(kind).max(other.get(kind)))
+        })
+    }
+
+    /// The strongest level of any kind.
+    pub fn level(self) -> u8 {
+        LightKind::ALL
+            .into_iter()
+            .map(|kind| self.get(kind))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Red, green and blue brightness vertex colours are multiplied by. Sky light is white, and
+    /// every level below [`MAX_LIGHT`] dims the light by a fifth.
+    pub fn brightness(self) -> [f32; 3] {
+        let sky = self.sky();
+
+        self.block().map(|level| {
+            0.8f32
+                .powi((MAX_LIGHT - level.max(sky)) as i32)
+                .max(MIN_BRIGHTNESS)
+        })
+    }
+}
+
+/// Light of every block in a chunk, indexed like its blocks.
+///
+/// Chunks that are lit the same everywhere, like open sky or solid rock, keep a single value.
+#[derive(Clone, Debug)]
+pub enum LightStorage {
+    Uniform(Light),
+    Full(Box<[Light]>),
+}
+
+impl Default for LightStorage {
+    fn default() -> Self {
+        Self::Uniform(Light::FULL)
+    }
+}
+
+impl LightStorage {
+    pub fn get(&self, index: usize) -> Light {
+        match self {
+            Self::Uniform(light) => *light,
+            Self::Full(lights) => lights[index],
+        }
+    }
+
+    pub fn set(&mut self, index: usize, light: Light) {
+        match self {
+            Self::Uniform(current) if *current == light => {}
+            Self::Uniform(current) => {
+                let mut lights = vec![*current; ChunkShape::SIZE as usize].into_boxed_slice();
+
+                lights[index] = light;
+                *self = Self::Full(lights);
+            }
+            Self::Full(lights) => lights[index] = light,
+        }
+    }
+}
+
+impl Chunk {
+    pub fn get_light(&self, position: [u32; 3]) -> Light {
+        self.light.get(ChunkShape::linearize(position) as usize)
+    }
+
+    pub fn set_light(&mut self, position: [u32; 3], light: Light) {
+        self.light
+            .set(ChunkShape::linearize(position) as usize, light);
+    }
+
+    pub fn fill_light(&mut self, light: Light) {
+        self.light = LightStorage::Uniform(light);
+    }
+}
+
+/// Light work queued up by chunk installs and block edits, see [`Chunks::update_light`].
+#[derive(Clone, Debug, Default)]
+pub struct LightUpdates {
+    chunks: Vec<ChunkKey>,
+    blocks: Vec<IVec3>,
+}
+
+impl LightUpdates {
+    pub fn queue_chunk(&mut self, key: ChunkKey) {
+        if !self.chunks.contains(&key) {
+            self.chunks.push(key);
+        }
+    }
+
+    pub fn queue_block(&mut self, position: IVec3) {
+        self.blocks.push(position);
+    }
+
+    pub fn contains_chunk(&self, key: ChunkKey) -> bool {
+        self.chunks.contains(&key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.blocks.is_empty()
+    }
+}
+
+impl Chunks {
+    /// Lights the chunks and blocks queued since the last call and queues every chunk whose
+    /// light changed to be re-meshed.
+    ///
+    /// Once `deadline` passed, the chunks that weren't lit yet stay queued for the next call.
+    /// At least one chunk is lit every call, and blocks are always updated right away.
+    ///
+    /// Light only spreads through loaded chunks. Where the chunk above isn't loaded, sky light
+    /// falls in above the generated ground only, and loaded chunks missing from the container,
+    /// which hold nothing but air, count as open sky above it and as dark below it.
+    pub fn update_light(
+        &mut self,
+        materials: &Materials,
+        loaded: &LoadedChunks,
+        deadline: Instant,
+    ) {
+        let mut updates = self.take_light_updates();
+
+        if updates.is_empty() {
+            return;
+        }
+
+        // chunks lit from the top down already see the real light of the chunk above them
+        updates.chunks.sort_by_key(|key| -key.y);
+
+        let mut engine = LightEngine::new(self, loaded, materials);
+        let mut waiting = Vec::new();
+        let chunks = updates
+            .chunks
+            .into_iter()
+            .filter(|key| loaded.is_chunk_id_loaded(key));
+
+        for (lit, key) in chunks.enumerate() {
+            if lit > 0 && Instant::now() >= deadline {
+                waiting.push(key);
+                continue;
+            }
+
+            engine.light_chunk(key);
+        }
+
+        for position in updates.blocks {
+            engine.update_block(position);
+        }
+
+        let changed = engine.changed;
+
+        for key in waiting {
+            self.queue_light(key);
+        }
+
+        for key in changed {
+            if loaded.is_chunk_id_loaded(&key) {
+                self.mark_dirty(key);
+            }
+        }
+    }
+}
+
+/// Flood fills light through the loaded chunks, remembering which chunks have to be re-meshed.
+struct LightEngine<'a> {
+    chunks: &'a mut Chunks,
+    loaded: &'a LoadedChunks,
+    /// Visibility of every block id.
+    visibility: Vec<VoxelVisibility>,
+    /// Light given off by every block id.
+    emission: Vec<Light>,
+    changed: HashSet<ChunkKey>,
+    /// The last chunk looked up and whether it is loaded, the flood fill mostly stays inside
+    /// the same chunk.
+    last_loaded: Cell<Option<(ChunkKey, bool)>>,
+}
+
+impl<'a> LightEngine<'a> {
+    fn new(chunks: &'a mut Chunks, loaded: &'a LoadedChunks, materials: &Materials) -> Self {
+        Self {
+            chunks,
+            loaded,
+            visibility: (0..=u8::MAX)
+                .map(|id| materials.get_from_id(id).visibility())
+                .collect(),
+            emission: (0..=u8::MAX)
+                .map(|id| materials.get_from_id(id).emission())
+                .collect(),
+            changed: HashSet::default(),
+            last_loaded: Cell::new(None),
+        }
+    }
+
+    fn is_loaded(&self, key: ChunkKey) -> bool {
+        match self.last_loaded.get() {
+            Some((last, loaded)) if last == key => loaded,
+            _ => {
+                let loaded = self.loaded.is_chunk_id_loaded(&key);
+
+                self.last_loaded.set(Some((key, loaded)));
+                loaded
+            }
+        }
+    }
+
+    /// Light and block id at the world block `position`, or `None` if its chunk isn't loaded.
+    fn voxel(&self, position: IVec3) -> Option<(Light, u8)> {
+        let (key, local) = Chunks::split_world_position(position);
+
+        if !self.is_loaded(key) {
+            return None;
+        }
+
+        Some(match self.chunks.get(key) {
+            Some(chunk) => (chunk.get_light(local), chunk.get_block(local)),
+            None if self.is_open_sky(position) => (Light::FULL, 0),
+            None => (Light::DARK, 0),
+        })
+    }
+
+    /// Whether the world block `position` lies above the generated ground, unknown columns
+    /// aren't.
+    fn is_open_sky(&self, position: IVec3) -> bool {
+        self.chunks
+            .surface_at([position.x, position.z])
+            .is_some_and(|top| position.y > top)
+    }
+
+    /// Light at the world block `position`, or `None` if its chunk isn't loaded.
+    fn light(&self, position: IVec3) -> Option<Light> {
+        self.voxel(position).map(|(light, _)| light)
+    }
+
+    /// Changes the light at the world block `position`, returns `false` if there is no chunk to
+    /// hold it.
+    fn set_light(&mut self, position: IVec3, light: Light) -> bool {
+        let (key, local) = Chunks::split_world_position(position);
+        let Some(chunk) = self.chunks.get_mut(key) else {
+            return false;
+        };
+
+        if chunk.get_light(local) != light {
+            chunk.set_light(local, light);
+            self.changed.extend(Chunks::block_dependents(key, local));
+        }
+
+        true
+    }
+
+    fn visibility(&self, position: IVec3) -> VoxelVisibility {
+        self.visibility[self.chunks.get_block_world(position) as usize]
+    }
+
+    /// Level of `kind` light the block at `position` gives off.
+    fn emission(&self, position: IVec3, kind: LightKind) -> u8 {
+        self.emission[self.chunks.get_block_world(position) as usize].get(kind)
+    }
+
+    /// Level `kind` light of `level` has after moving one block along `direction` into a block
+    /// with `visibility`.
+    fn spread(kind: LightKind, level: u8, direction: IVec3, visibility: VoxelVisibility) -> u8 {
+        if kind == LightKind::Sky
+            && direction == IVec3::NEG_Y
+            && level == MAX_LIGHT
+            && visibility == VoxelVisibility::Empty
+        {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+
+    /// Spreads `kind` light outwards from every position in `queue`.
+    fn propagate(&mut self, kind: LightKind, mut queue: VecDeque<IVec3>) {
+        while let Some(position) = queue.pop_front() {
+            let Some(level) = self.light(position).map(|light| light.get(kind)) else {
+                continue;
+            };
+
+            if level <= 1 {
+                continue;
+            }
+
+            for direction in DIRECTIONS {
+                let next = position + direction;
+                let Some((light, id)) = self.voxel(next) else {
+                    continue;
+                };
+
+                let visibility = self.visibility[id as usize];
+
+                if visibility == VoxelVisibility::Opaque {
+                    continue;
+                }
+
+                let spread = Self::spread(kind, level, direction, visibility);
+
+                if light.get(kind) < spread && self.set_light(next, light.with(kind, spread)) {
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+
+    /// Darkens everything lit by the positions in `removals`, which were already set to zero
+    /// and had the paired level before, and returns the positions lit by other sources that
+    /// have to spread their light back into the darkened area.
+    ///
+    /// Darkened blocks that give off light themselves keep their own light and become sources.
+    fn remove(&mut self, kind: LightKind, mut removals: VecDeque<(IVec3, u8)>) -> VecDeque<IVec3> {
+        let mut sources = VecDeque::new();
+
+        while let Some((position, level)) = removals.pop_front() {
+            for direction in DIRECTIONS {
+                let next = position + direction;
+                let Some((light, id)) = self.voxel(next) else {
+                    continue;
+                };
+
+                let next_level = light.get(kind);
+
+                if next_level == 0 {
+                    continue;
+                }
+
+                // sunlight falling straight down is as strong as what it fell from
+                let lit_by_position = next_level < level
+                    || (kind == LightKind::Sky
+                        && direction == IVec3::NEG_Y
+                        && level == MAX_LIGHT
+                        && next_level == MAX_LIGHT);
+
+                let emission = self.emission[id as usize].get(kind);
+
+                if lit_by_position && self.set_light(next, light.with(kind, emission)) {
+                    removals.push_back((next, next_level));
+
+                    if emission > 0 {
+                        sources.push_back(next);
+                    }
+                } else {
+                    sources.push_back(next);
+                }
+            }
+        }
+
+        sources
+    }
+
+    /// Lights the chunk at `key` from scratch, pulling in the light of its loaded neighbours.
+    fn light_chunk(&mut self, key: ChunkKey) {
+        let origin = IVec3::from(Chunks::domain_origin(key.to_array()));
+        let mut queues = LightKind::ALL.map(|_| VecDeque::new());
+
+        let Some(chunk) = self.chunks.get_mut(key) else {
+            // a chunk of only air is open sky above the ground, hand that to its neighbours
+            let sky = DIRECTIONS
+                .into_iter()
+                .flat_map(|direction| border(origin, direction))
+                .collect();
+
+            self.propagate(LightKind::Sky, sky);
+            return;
+        };
+
+        chunk.fill_light(Light::DARK);
+
+        // blocks giving off light
+        for i in 0..ChunkShape::SIZE {
+            let emission = self.emission[chunk.get_block_domain(i as usize) as usize];
+
+            if emission == Light::DARK {
+                continue;
+            }
+
+            let local = ChunkShape::delinearize(i);
+            let position = origin + IVec3::from_array(local.map(|axis| axis as i32));
+
+            chunk.light.set(i as usize, emission);
+
+            for (kind, queue) in LightKind::ALL.into_iter().zip(&mut queues) {
+                if emission.get(kind) > 0 {
+                    queue.push_back(position);
+                }
+            }
+        }
+
+        self.changed.insert(key);
+
+        // columns the sky shines into from above
+        for z in 0..Z_SIZE as i32 {
+            for x in 0..X_SIZE as i32 {
+                let top = origin + IVec3::new(x, Y_SIZE as i32 - 1, z);
+
+                // past the loaded chunks, the generated ground tells whether the sky is open
+                let open = match self.light(top + IVec3::Y) {
+                    Some(light) => light.sky() == MAX_LIGHT,
+                    None => self.is_open_sky(top + IVec3::Y),
+                };
+
+                if !open {
+                    continue;
+                }
+
+                for y in (0..Y_SIZE as i32).rev() {
+                    let position = origin + IVec3::new(x, y, z);
+
+                    if self.visibility(position) != VoxelVisibility::Empty {
+                        break;
+                    }
+
+                    let light = self.light(position).unwrap_or_default();
+
+                    self.set_light(position, light.with(LightKind::Sky, MAX_LIGHT));
+                    queues[0].push_back(position);
+                }
+            }
+        }
+
+        // light reaching in from the neighbours
+        for direction in DIRECTIONS {
+            for position in border(origin, direction) {
+                let outside = position + direction;
+                let Some(light) = self.light(outside) else {
+                    continue;
+                };
+
+                for (kind, queue) in LightKind::ALL.into_iter().zip(&mut queues) {
+                    if light.get(kind) > 1 {
+                        queue.push_back(outside);
+                    }
+                }
+            }
+        }
+
+        for (kind, queue) in LightKind::ALL.into_iter().zip(queues) {
+            self.propagate(kind, queue);
+        }
+
+        // the chunk below took its top to be open sky as long as this one was missing, when the
+        // ground didn't cover it
+        let mut removals = VecDeque::new();
+
+        for position in border(origin, IVec3::NEG_Y) {
+            if self.light(position).map(Light::sky) == Some(MAX_LIGHT) {
+                continue;
+            }
+
+            let below = position - IVec3::Y;
+            let Some(light) = self.light(below) else {
+                continue;
+            };
+
+            if light.sky() == MAX_LIGHT && self.set_light(below, light.with(LightKind::Sky, 0)) {
+                removals.push_back((below, MAX_LIGHT));
+            }
+        }
+
+        let sources = self.remove(LightKind::Sky, removals);
+        self.propagate(LightKind::Sky, sources);
+    }
+
+    /// Updates the light around the block at `position` after it changed.
+    fn update_block(&mut self, position: IVec3) {
+        for kind in LightKind::ALL {
+            let Some(light) = self.light(position) else {
+                return;
+            };
+
+            let level = light.get(kind);
+            let mut sources = VecDeque::new();
+
+            if level > 0 && self.set_light(position, light.with(kind, 0)) {
+                sources = self.remove(kind, VecDeque::from([(position, level)]));
+            }
+
+            let emission = self.emission(position, kind);
+
+            if emission > 0 {
+                let light = self.light(position).unwrap_or_default();
+
+                self.set_light(position, light.with(kind, emission));
+                sources.push_back(position);
+            }
+
+            // light flows back in from the neighbours when the block lets it through
+            if self.visibility(position) != VoxelVisibility::Opaque {
+                sources.extend(DIRECTIONS.map(|direction| position + direction));
+            }
+
+            self.propagate(kind, sources);
+        }
+    }
+}
+
+/// World positions of the blocks on the side facing `direction` of the chunk at `origin`.
+fn border(origin: IVec3, direction: IVec3) -> impl Iterator<Item = IVec3> {
+    let size = IVec3::new(X_SIZE as i32, Y_SIZE as i32, Z_SIZE as i32);
+    let axis = (0..3).find(|axis| direction[*axis] != 0).unwrap();
+    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+    let side = if direction[axis] > 0 {
+        size[axis] - 1
+    } else {
+        0
+    };
+
+    (0..size[u]).flat_map(move |a| {
+        (0..size[v]).map(move |b| {
+            let mut position = IVec3::ZERO;
+
+            position[axis] = side;
+            position[u] = a;
+            position[v] = b;
+
+            origin + position
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    };
+
+    use bevy::prelude::{IVec2, IVec3};
+
+    use super::{Light, LightEngine, LightKind, MAX_LIGHT};
+    use crate::{
+        chunk::{
+            container::{loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk},
+            storage::ChunkStorage,
+            X_SIZE, Z_SIZE,
+        },
+        material::Materials,
+    };
+
+    /// Chunks `-1..=1` along x and z and `-1..=0` along y, with stone below y = 0.
+    fn ground() -> (Chunks, LoadedChunks) {
+        let mut chunks = Chunks::default();
+        let mut loaded = LoadedChunks::default();
+
+        for z in -1..=1 {
+            for x in -1..=1 {
+                surface(&mut chunks, IVec2::new(x, z), -1);
+            }
+
+            for y in -1..=0 {
+                for x in -1..=1 {
+                    let key = ChunkKey::new(x, y, z);
+                    let id = if y < 0 { 2 } else { 0 };
+
+                    chunks
+                        .get_domain_at_mut(key.to_array())
+                        .override_blocks(ChunkStorage::Uniform(id));
+                    loaded.add_rendered_chunk(key);
+                    chunks.queue_light(key);
+                }
+            }
+        }
+
+        (chunks, loaded)
+    }
+
+    /// Generated ground with its top at `top` in every block column of `column`.
+    fn surface(chunks: &mut Chunks, column: IVec2, top: i32) {
+        chunks.insert_surface(column, vec![top; X_SIZE * Z_SIZE].into_boxed_slice());
+    }
+
+    /// A deadline far enough away for every queued chunk to be lit.
+    fn unbudgeted() -> Instant {
+        Instant::now() + Duration::from_secs(60)
+    }
+
+    fn sky(chunks: &Chunks, position: IVec3) -> u8 {
+        let (key, local) = Chunks::split_world_position(position);
+
+        chunks.get(key).unwrap().get_light(local).sky()
+    }
+
+    #[test]
+    pub fn skylight_test() {
+        let materials = Materials::default();
+        let (mut chunks, loaded) = ground();
+
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(sky(&chunks, IVec3::new(5, 0, 5)), MAX_LIGHT);
+        assert_eq!(sky(&chunks, IVec3::new(5, -1, 5)), 0);
+
+        // dig a shaft with a side tunnel crossing into the next chunk
+        for y in -10..0 {
+            chunks.set_block_world(IVec3::new(30, y, 5), 0);
+        }
+
+        for x in 31..40 {
+            chunks.set_block_world(IVec3::new(x, -10, 5), 0);
+        }
+
+        chunks.pull_dirty();
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(sky(&chunks, IVec3::new(30, -10, 5)), MAX_LIGHT);
+        assert_eq!(sky(&chunks, IVec3::new(35, -10, 5)), MAX_LIGHT - 5);
+        assert_eq!(sky(&chunks, IVec3::new(39, -10, 5)), MAX_LIGHT - 9);
+
+        // the tunnel's chunk gets re-meshed for its new light
+        assert!(chunks.pull_dirty().contains(&ChunkKey::new(1, -1, 0)));
+
+        // covering the shaft darkens everything below it again
+        chunks.set_block_world(IVec3::new(30, -1, 5), 2);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(sky(&chunks, IVec3::new(30, -10, 5)), 0);
+        assert_eq!(sky(&chunks, IVec3::new(35, -10, 5)), 0);
+        assert_eq!(sky(&chunks, IVec3::new(30, 0, 5)), MAX_LIGHT);
+
+        // glass lets the light through, but it isn't direct sunlight below it anymore
+        chunks.set_block_world(IVec3::new(30, -1, 5), 9);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(sky(&chunks, IVec3::new(30, -1, 5)), MAX_LIGHT - 1);
+        assert_eq!(sky(&chunks, IVec3::new(30, -10, 5)), MAX_LIGHT - 10);
+    }
+
+    #[test]
+    pub fn block_light_test() {
+        let materials = Materials::default();
+        let lamp = materials.id_of("lamp").unwrap();
+        let (mut chunks, loaded) = ground();
+
+        // a lamp in a closed cave, next to the border to the chunk at x = 1
+        for x in 28..40 {
+            chunks.set_block_world(IVec3::new(x, -16, 5), 0);
+        }
+
+        chunks.set_block_world(IVec3::new(28, -16, 5), lamp);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        let light = |chunks: &Chunks, x| {
+            let (key, local) = Chunks::split_world_position(IVec3::new(x, -16, 5));
+
+            chunks.get(key).unwrap().get_light(local)
+        };
+
+        let emission = materials.get_from_id(lamp).emission().block();
+
+        assert_eq!(light(&chunks, 28).block(), emission);
+        assert_eq!(light(&chunks, 34).block(), emission.map(|level| level - 6));
+        assert_eq!(light(&chunks, 34).sky(), 0);
+
+        // lighting the chunk again from scratch gives the same result
+        chunks.queue_light(ChunkKey::new(1, -1, 0));
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(light(&chunks, 34).block(), emission.map(|level| level - 6));
+
+        // taking the lamp away darkens the cave again
+        chunks.set_block_world(IVec3::new(28, -16, 5), 0);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert!((28..40).all(|x| light(&chunks, x) == Light::DARK));
+    }
+
+    #[test]
+    pub fn block_light_spread_test() {
+        let materials = Materials::default();
+        let (mut chunks, loaded) = ground();
+        let source = IVec3::new(28, -16, 5);
+
+        // a closed cave running across the border to the chunk at x = 1
+        for x in 24..40 {
+            chunks.set_block_world(IVec3::new(x, -16, 5), 0);
+        }
+
+        chunks.update_light(&materials, &loaded, unbudgeted());
+        chunks.pull_dirty();
+
+        let light = |chunks: &Chunks, x| {
+            let (key, local) = Chunks::split_world_position(IVec3::new(x, -16, 5));
+
+            chunks.get(key).unwrap().get_light(local)
+        };
+
+        // red light set by hand spreads out one level weaker per block, green and blue stay dark
+        let mut engine = LightEngine::new(&mut chunks, &loaded, &materials);
+
+        engine.set_light(source, Light::new(0, [12, 0, 0]));
+        engine.propagate(LightKind::Red, VecDeque::from([source]));
+
+        let changed = engine.changed;
+
+        assert!(changed.contains(&ChunkKey::new(1, -1, 0)));
+        assert_eq!(light(&chunks, 24).block(), [8, 0, 0]);
+        assert_eq!(light(&chunks, 35).block(), [5, 0, 0]);
+        assert_eq!(light(&chunks, 39).block(), [1, 0, 0]);
+        assert_eq!(light(&chunks, 35).sky(), 0);
+
+        // taking it away again darkens the whole cave, on both sides of the border
+        let mut engine = LightEngine::new(&mut chunks, &loaded, &materials);
+
+        engine.set_light(source, Light::DARK);
+
+        let sources = engine.remove(LightKind::Red, VecDeque::from([(source, 12)]));
+
+        engine.propagate(LightKind::Red, sources);
+
+        assert!((24..40).all(|x| light(&chunks, x) == Light::DARK));
+    }
+
+    #[test]
+    pub fn light_chunk_order_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+        let mut loaded = LoadedChunks::default();
+
+        // a cave chunk lit before the solid chunk above it was loaded takes it for open sky, as
+        // long as the generated ground lies below it
+        let cave = ChunkKey::new(0, -1, 0);
+
+        surface(&mut chunks, IVec2::ZERO, -40);
+
+        chunks
+            .get_domain_at_mut(cave.to_array())
+            .override_blocks(ChunkStorage::Uniform(0));
+        loaded.add_rendered_chunk(cave);
+        chunks.queue_light(cave);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(sky(&chunks, IVec3::new(3, -20, 3)), MAX_LIGHT);
+
+        let roof = ChunkKey::new(0, 0, 0);
+
+        chunks
+            .get_domain_at_mut(roof.to_array())
+            .override_blocks(ChunkStorage::Uniform(2));
+        loaded.add_rendered_chunk(roof);
+        chunks.queue_light(roof);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        let cave = chunks.get(cave).unwrap();
+
+        assert!((0..32).all(|y| cave.get_light([3, y, 3]) == Light::DARK));
+    }
+
+    #[test]
+    pub fn surface_skylight_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+        let mut loaded = LoadedChunks::default();
+
+        // a cave far below the ground, with nothing above it loaded, and a chunk of only air
+        // next to it that isn't in the container
+        let cave = ChunkKey::new(0, -4, 0);
+        let air = ChunkKey::new(1, -4, 0);
+
+        surface(&mut chunks, IVec2::new(0, 0), 20);
+        surface(&mut chunks, IVec2::new(1, 0), 20);
+        chunks
+            .get_domain_at_mut(cave.to_array())
+            .override_blocks(ChunkStorage::Uniform(0));
+
+        for key in [cave, air] {
+            loaded.add_rendered_chunk(key);
+            chunks.queue_light(key);
+        }
+
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        let cave = chunks.get(cave).unwrap();
+
+        assert!((0..32).all(|y| cave.get_light([3, y, 3]) == Light::DARK));
+        assert!((0..32).all(|y| cave.get_light([31, y, 3]) == Light::DARK));
+
+        // above the ground, the edge of the loaded chunks is open sky
+        let open = ChunkKey::new(0, 1, 0);
+
+        chunks
+            .get_domain_at_mut(open.to_array())
+            .override_blocks(ChunkStorage::Uniform(0));
+        loaded.add_rendered_chunk(open);
+        chunks.queue_light(open);
+        chunks.update_light(&materials, &loaded, unbudgeted());
+
+        assert_eq!(sky(&chunks, IVec3::new(3, 32, 3)), MAX_LIGHT);
+        assert_eq!(sky(&chunks, IVec3::new(3, 63, 3)), MAX_LIGHT);
+    }
+
+    #[test]
+    pub fn light_budget_test() {
+        let materials = Materials::default();
+        let (mut chunks, loaded) = ground();
+
+        // past the deadline, only the first chunk gets lit and the rest wait for the next frame
+        chunks.update_light(&materials, &loaded, Instant::now());
+
+        let waiting = (-1..=1)
+            .flat_map(|z| (-1..=0).flat_map(move |y| (-1..=1).map(move |x| ChunkKey::new(x, y, z))))
+            .filter(|key| chunks.is_light_queued(*key))
+            .count();
+
+        assert_eq!(waiting, 17);
+
+        while chunks.has_light_updates() {
+            chunks.update_light(&materials, &loaded, Instant::now());
+        }
+
+        assert_eq!(sky(&chunks, IVec3::new(5, 0, 5)), MAX_LIGHT);
+        assert_eq!(sky(&chunks, IVec3::new(5, -1, 5)), 0);
+    }
+
+    #[test]
+    pub fn light_packing_test() {
+        let light = Light::new(12, [3, 0, 14]);
+
+        assert_eq!(
+            (light.sky(), light.block(), light.level()),
+            (12, [3, 0, 14], 14)
+        );
+        assert_eq!(light.with(LightKind::Green, 20).block(), [3, MAX_LIGHT, 14]);
+        assert_eq!(
+            light.max(Light::new(13, [2, 5, 1])),
+            Light::new(13, [3, 5, 14])
+        );
+        assert_eq!(Light::FULL.brightness(), [1.0; 3]);
+        assert!(Light::DARK.brightness()[0] > 0.0);
+    }
+}
@@ -0,0 +1,102 @@
+use super::{
+    container::{ChunkKey, Chunks},
+    Chunk,
+};
+
+/// Coarsest level of detail, where one voxel covers `2^MAX_LOD` blocks along every axis.
+pub const MAX_LOD: u8 = 3;
+
+/// Chunk distance from the camera up to which each level of detail below [`MAX_LOD`] is used.
+pub const LOD_DISTANCES: [i32; MAX_LOD as usize] = [2, 4, 6];
+
+/// Level of detail of a chunk `distance` chunks away from the camera along its furthest axis.
+pub fn lod_for_distance(distance: i32) -> u8 {
+    LOD_DISTANCES
+        .iter()
+        .filter(|threshold| distance > **threshold)
+        .count() as u8
+}
+
+impl Chunk {
+    /// Block id of the `scale`-sized cube of blocks at `cell`, in units of `scale` blocks.
+    ///
+    /// The cube is solid when at least half of its blocks are, and then takes the most common
+    /// solid block, so thin layers of air don't punch holes into coarse terrain.
+    pub fn downsampled_block(&self, [x, y, z]: [u32; 3], scale: u32) -> u8 {
+        if scale == 1 {
+            return self.get_block([x, y, z]);
+        }
+
+        let mut counts = [0u16; 256];
+        let mut solid = 0;
+
+        for inner_z in 0..scale {
+            for inner_y in 0..scale {
+                for inner_x in 0..scale {
+                    let id = self.get_block([
+                        x * scale + inner_x,
+                        y * scale + inner_y,
+                        z * scale + inner_z,
+                    ]);
+
+                    if id != 0 {
+                        counts[id as usize] += 1;
+                        solid += 1;
+                    }
+                }
+            }
+        }
+
+        if solid * 2 < scale.pow(3) {
+            return 0;
+        }
+
+        // ties go to the lower id, so the result doesn't depend on block order
+        (1..=u8::MAX)
+            .max_by_key(|id| (counts[*id as usize], u8::MAX - id))
+            .unwrap()
+    }
+}
+
+impl Chunks {
+    /// Level of detail the chunk at `key` should be meshed at, see [`Chunks::set_lod_center`].
+    pub fn lod_at(&self, key: ChunkKey) -> u8 {
+        lod_for_distance((key - self.lod_center()).abs().max_element())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::IVec3;
+
+    use super::lod_for_distance;
+    use crate::chunk::{storage::ChunkStorage, Chunk};
+
+    #[test]
+    pub fn lod_distance_test() {
+        let lods = (0..10).map(lod_for_distance).collect::<Vec<_>>();
+
+        assert_eq!(lods, [0, 0, 0, 1, 1, 2, 2, 3, 3, 3]);
+    }
+
+    #[test]
+    pub fn majority_vote_test() {
+        let mut chunk = Chunk::new(IVec3::ZERO);
+
+        // a 2x2x2 cell with three stone, one sand and four air blocks stays solid
+        for position in [[0, 0, 0], [1, 0, 0], [0, 1, 0]] {
+            chunk.set_block(position, 2);
+        }
+        chunk.set_block([1, 1, 0], 4);
+
+        assert_eq!(chunk.downsampled_block([0, 0, 0], 2), 2);
+
+        // less than half solid turns into air
+        chunk.set_block([0, 1, 0], 0);
+        assert_eq!(chunk.downsampled_block([0, 0, 0], 2), 0);
+
+        chunk.override_blocks(ChunkStorage::Uniform(3));
+        assert_eq!(chunk.downsampled_block([1, 2, 3], 8), 3);
+        assert_eq!(chunk.downsampled_block([1, 2, 3], 1), 3);
+    }
+}
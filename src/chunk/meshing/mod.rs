@@ -0,0 +1,1007 @@
+use bevy::{
+    prelude::{IVec3, Mesh, Resource, UVec3, Vec3},
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use block_mesh::{
+    GreedyQuadsBuffer, MergeVoxel, UnorientedQuad, VoxelVisibility, RIGHT_HANDED_Y_UP_CONFIG,
+};
+use futures_lite::future;
+use ndshape::{RuntimeShape, Shape};
+
+use crate::material::Materials;
+
+use super::{
+    collider::CollisionMesh,
+    container::{ChunkKey, Chunks},
+    light::Light,
+    voxel::{Voxel, VOID},
+    Chunk, X_SIZE_U32, Y_SIZE_U32, Z_SIZE_U32,
+};
+
+mod smooth;
+
+/// How chunk voxels are turned into geometry, chosen per world.
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MeshingMode {
+    /// Every voxel is a cube.
+    #[default]
+    Blocky,
+    /// Every solid voxel is part of a smooth surface.
+    Smooth,
+    /// Voxels are smooth when their material is marked `smooth`, e.g. smooth rock next to
+    /// blocky buildings.
+    PerMaterial,
+}
+
+/// Snapshot of a chunk's voxels, padded with the bordering layer of each of
+/// its neighbours so faces between chunks can be culled and occluded.
+///
+/// Chunks with a level of detail are snapshotted at that resolution, see [`Chunk::lod`].
+pub struct PaddedChunk {
+    voxels: Vec<Voxel>,
+    /// Light of every voxel, coarse chunks and missing neighbours are fully lit.
+    light: Vec<Light>,
+    /// Generated density of every voxel, see [`Chunk::get_density`], missing for coarse chunks.
+    density: Vec<Option<f32>>,
+    shape: RuntimeShape<u32, 3>,
+    /// Blocks covered by a voxel along every axis.
+    scale: u32,
+}
+
+/// The meshes of a chunk, translucent faces like water are kept apart so they can be drawn
+/// with blending after everything opaque.
+pub struct ChunkMesh {
+    pub opaque: Mesh,
+    pub transparent: Mesh,
+    /// The faces of solid blocks, for the chunk's collider.
+    pub collision: CollisionMesh,
+}
+
+/// Chunk meshes being built on the [`AsyncComputeTaskPool`], at most one per chunk.
+#[derive(Resource, Default)]
+pub struct MeshingJobs {
+    running: HashMap<ChunkKey, (u64, Task<ChunkMesh>)>,
+}
+
+impl MeshingJobs {
+    /// Meshes `view` in the background, cancelling any job still running for `key`.
+    ///
+    /// `revision` is handed back with the mesh, so results for a chunk that changed in the
+    /// meantime can be told apart.
+    pub fn spawn(
+        &mut self,
+        key: ChunkKey,
+        revision: u64,
+        view: PaddedChunk,
+        materials: Materials,
+        mode: MeshingMode,
+    ) {
+        let task = AsyncComputeTaskPool::get().spawn(async move { view.mesh(&materials, mode) });
+
+        self.running.insert(key, (revision, task));
+    }
+
+    pub fn cancel(&mut self, key: ChunkKey) {
+        self.running.remove(&key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    /// Removes and returns the meshes that finished since the last call, with their revision.
+    pub fn pull_finished(&mut self) -> Vec<(ChunkKey, u64, ChunkMesh)> {
+        let finished = self
+            .running
+            .iter_mut()
+            .filter_map(|(key, (revision, task))| {
+                future::block_on(future::poll_once(task)).map(|mesh| (*key, *revision, mesh))
+            })
+            .collect::<Vec<_>>();
+
+        for (key, _, _) in &finished {
+            self.running.remove(key);
+        }
+
+        finished
+    }
+}
+
+impl Chunks {
+    /// Snapshots the chunk at `key` and its neighbours at the chunk's level of detail.
+    ///
+    /// Neighbours at a different level of detail are left out of the padding, so both sides of
+    /// the border keep their faces there and the seam between the two resolutions stays closed.
+    pub fn padded_view(&self, key: ChunkKey) -> PaddedChunk {
+        let lod = self.get(key).map_or(0, |chunk| chunk.lod);
+        let scale = 1 << lod;
+        let size = IVec3::new(X_SIZE_U32 as i32, Y_SIZE_U32 as i32, Z_SIZE_U32 as i32) / scale;
+        let shape = RuntimeShape::<u32, 3>::new((size + 2).as_uvec3().to_array());
+        let mut voxels = vec![VOID; shape.usize()];
+        let mut light = vec![Light::FULL; shape.usize()];
+        let mut density = vec![None; shape.usize()];
+
+        // indexed by `offset + 1`, linearized the same way as a 3x3x3 shape
+        let mut neighbours: [Option<&Chunk>; 27] = [None; 27];
+
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let offset = IVec3::new(x, y, z);
+
+                    neighbours[Self::neighbour_index(offset)] =
+                        self.get(key + offset).filter(|chunk| chunk.lod == lod);
+                }
+            }
+        }
+
+        for (i, ((voxel, light), density)) in voxels
+            .iter_mut()
+            .zip(&mut light)
+            .zip(&mut density)
+            .enumerate()
+        {
+            let padded = shape.delinearize(i as u32);
+            let local = IVec3::from_array(padded.map(|axis| axis as i32 - 1));
+            let offset = IVec3::new(
+                local.x.div_euclid(size.x),
+                local.y.div_euclid(size.y),
+                local.z.div_euclid(size.z),
+            );
+
+            if let Some(chunk) = neighbours[Self::neighbour_index(offset)] {
+                let inner = local - offset * size;
+
+                *voxel = Voxel {
+                    id: chunk.downsampled_block(inner.as_uvec3().to_array(), scale as u32),
+                };
+
+                if scale == 1 {
+                    *light = chunk.get_light(inner.as_uvec3().to_array());
+                    *density = chunk.get_density(inner.as_uvec3().to_array());
+                }
+            }
+        }
+
+        PaddedChunk {
+            voxels,
+            light,
+            density,
+            shape,
+            scale: scale as u32,
+        }
+    }
+
+    fn neighbour_index(offset: IVec3) -> usize {
+        let [x, y, z] = (offset + IVec3::ONE).to_array();
+
+        (x + y * 3 + z * 9) as usize
+    }
+}
+
+/// Brightness applied to a vertex for each of its ambient occlusion levels.
+pub const AO_CURVE: [f32; 4] = [0.45, 0.65, 0.85, 1.0];
+
+/// A voxel together with its material's visibility, the packed ambient occlusion of the
+/// four corners of each of its faces and the light in front of them, so greedy merging keeps
+/// quads with differing occlusion or light apart.
+#[derive(Clone, Copy)]
+struct OccludedVoxel {
+    voxel: Voxel,
+    visibility: VoxelVisibility,
+    ao: [u8; 6],
+    light: [Light; 6],
+}
+
+impl block_mesh::Voxel for OccludedVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        self.visibility
+    }
+}
+
+impl MergeVoxel for OccludedVoxel {
+    type MergeValue = (u8, [u8; 6], [Light; 6]);
+
+    fn merge_value(&self) -> Self::MergeValue {
+        (self.voxel.id, self.ao, self.light)
+    }
+}
+
+/// A voxel as the collision mesher sees it, either solid or not.
+#[derive(Clone, Copy)]
+struct SolidVoxel(bool);
+
+impl block_mesh::Voxel for SolidVoxel {
+    fn get_visibility(&self) -> VoxelVisibility {
+        match self.0 {
+            true => VoxelVisibility::Opaque,
+            false => VoxelVisibility::Empty,
+        }
+    }
+}
+
+impl MergeVoxel for SolidVoxel {
+    type MergeValue = bool;
+
+    fn merge_value(&self) -> Self::MergeValue {
+        self.0
+    }
+}
+
+impl PaddedChunk {
+    pub fn get(&self, [x, y, z]: [u32; 3]) -> Voxel {
+        self.voxels[self.shape.linearize([x + 1, y + 1, z + 1]) as usize]
+    }
+
+    /// Voxels along each axis of the chunk, without the padding.
+    fn interior_size(&self) -> UVec3 {
+        UVec3::from_array(self.shape.as_array()) - 2
+    }
+
+    /// Moves a position in padded voxel space back into chunk block space.
+    fn chunk_position(&self, position: [f32; 3]) -> [f32; 3] {
+        position.map(|axis| (axis - 1.0) * self.scale as f32)
+    }
+
+    /// Visibility of every padded voxel, looked up from its material.
+    fn visibility(&self, materials: &Materials) -> Vec<VoxelVisibility> {
+        self.voxels
+            .iter()
+            .map(|voxel| materials.get_from_id(voxel.id).visibility())
+            .collect()
+    }
+
+    fn is_solid(&self, visibility: &[VoxelVisibility], position: IVec3) -> bool {
+        visibility[self.shape.linearize(position.as_uvec3().to_array()) as usize]
+            == VoxelVisibility::Opaque
+    }
+
+    /// Ambient occlusion (`0` darkest, `3` unoccluded) of the four corners of the face of the
+    /// voxel at `position` pointing towards `normal`, in the order of `quad_mesh_positions`.
+    fn face_ao(
+        &self,
+        visibility: &[VoxelVisibility],
+        position: IVec3,
+        normal: IVec3,
+        u: IVec3,
+        v: IVec3,
+    ) -> [u8; 4] {
+        let front = position + normal;
+
+        [(-1, -1), (1, -1), (-1, 1), (1, 1)].map(|(su, sv)| {
+            let side_u = self.is_solid(visibility, front + u * su);
+            let side_v = self.is_solid(visibility, front + v * sv);
+            let corner = self.is_solid(visibility, front + u * su + v * sv);
+
+            if side_u && side_v {
+                0
+            } else {
+                3 - side_u as u8 - side_v as u8 - corner as u8
+            }
+        })
+    }
+
+    /// Meshes the chunk, sending the opaque voxels `mode` picks out through surface nets and the
+    /// rest through greedy quads.
+    pub fn mesh(&self, materials: &Materials, mode: MeshingMode) -> ChunkMesh {
+        let is_smooth = |voxel: Voxel| {
+            materials.get_from_id(voxel.id).visibility() == VoxelVisibility::Opaque
+                && match mode {
+                    MeshingMode::Blocky => false,
+                    MeshingMode::Smooth => true,
+                    MeshingMode::PerMaterial => materials.get_from_id(voxel.id).smooth,
+                }
+        };
+
+        let solid = |voxel: Voxel| materials.get_from_id(voxel.id).solid;
+
+        if mode == MeshingMode::Blocky {
+            let (opaque, transparent) = self.greedy_mesh(materials);
+
+            return ChunkMesh {
+                opaque: opaque.into_mesh(),
+                transparent: transparent.into_mesh(),
+                collision: self.collision_mesh(solid),
+            };
+        }
+
+        // smooth voxels count as air to the blocky mesher so the faces around them stay closed
+        let blocky = PaddedChunk {
+            voxels: self
+                .voxels
+                .iter()
+                .map(|voxel| if is_smooth(*voxel) { VOID } else { *voxel })
+                .collect(),
+            light: self.light.clone(),
+            density: self.density.clone(),
+            shape: self.shape.clone(),
+            scale: self.scale,
+        };
+
+        let (mut opaque, transparent) = blocky.greedy_mesh(materials);
+        let smooth = self.smooth_mesh(materials, is_smooth);
+
+        // smooth voxels collide through their smooth surface, unless they aren't solid, like lava
+        let mut collision = self.collision_mesh(|voxel| solid(voxel) && !is_smooth(voxel));
+
+        if self
+            .voxels
+            .iter()
+            .all(|voxel| !is_smooth(*voxel) || solid(*voxel))
+        {
+            collision.extend(&smooth.positions, &smooth.indices);
+        } else {
+            let solid_smooth =
+                self.smooth_mesh(materials, |voxel| is_smooth(voxel) && solid(voxel));
+
+            collision.extend(&solid_smooth.positions, &solid_smooth.indices);
+        }
+
+        opaque.append(smooth);
+
+        ChunkMesh {
+            opaque: opaque.into_mesh(),
+            transparent: transparent.into_mesh(),
+            collision,
+        }
+    }
+
+    /// Meshes every voxel as cubes, returning the opaque and the translucent faces apart.
+    fn greedy_mesh(&self, materials: &Materials) -> (MeshData, MeshData) {
+        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+        let unit_quad = UnorientedQuad {
+            minimum: [1; 3],
+            width: 1,
+            height: 1,
+        };
+
+        // the u and v axes of a face are the edges of a unit quad
+        let axes = faces.map(|face| {
+            let [origin, max_u, max_v, _] = face
+                .quad_mesh_positions(&unit_quad, 1.0)
+                .map(|position| Vec3::from_array(position).as_ivec3());
+
+            (
+                IVec3::from_array(face.signed_normal().to_array()),
+                max_u - origin,
+                max_v - origin,
+            )
+        });
+
+        let interior_max = self.interior_size().as_ivec3();
+        let visibility = self.visibility(materials);
+        let voxels = self
+            .voxels
+            .iter()
+            .zip(&visibility)
+            .enumerate()
+            .map(|(i, (voxel, voxel_visibility))| {
+                let position =
+                    IVec3::from_array(self.shape.delinearize(i as u32).map(|axis| axis as i32));
+                let interior =
+                    position.cmpge(IVec3::ONE).all() && position.cmple(interior_max).all();
+
+                let visible = axes.map(|(normal, _, _)| {
+                    interior
+                        && *voxel_visibility != VoxelVisibility::Empty
+                        && !self.is_solid(&visibility, position + normal)
+                });
+                let ao = [0, 1, 2, 3, 4, 5].map(|side| {
+                    let (normal, u, v) = axes[side];
+
+                    if visible[side] {
+                        let [a, b, c, d] = self.face_ao(&visibility, position, normal, u, v);
+
+                        a | b << 2 | c << 4 | d << 6
+                    } else {
+                        u8::MAX
+                    }
+                });
+                // faces are lit by the block they face, hidden ones count as fully lit just like
+                // they count as unoccluded, so they merge with the open sky around them
+                let light = [0, 1, 2, 3, 4, 5].map(|side| {
+                    if visible[side] {
+                        self.light[self
+                            .shape
+                            .linearize((position + axes[side].0).as_uvec3().to_array())
+                            as usize]
+                    } else {
+                        Light::FULL
+                    }
+                });
+
+                OccludedVoxel {
+                    voxel: *voxel,
+                    visibility: *voxel_visibility,
+                    ao,
+                    light,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut buffer = GreedyQuadsBuffer::new(voxels.len());
+
+        block_mesh::greedy_quads(
+            &voxels,
+            &self.shape,
+            [0; 3],
+            (self.interior_size() + 1).to_array(),
+            &faces,
+            &mut buffer,
+        );
+
+        let mut opaque = MeshData::default();
+        let mut transparent = MeshData::default();
+
+        for (side, (group, face)) in buffer.quads.groups.into_iter().zip(faces).enumerate() {
+            for quad in group.into_iter() {
+                let OccludedVoxel {
+                    voxel,
+                    visibility,
+                    ao,
+                    light,
+                } = voxels[self.shape.linearize(quad.minimum) as usize];
+                let ao = [0, 2, 4, 6].map(|shift| (ao[side] >> shift) & 0b11);
+                let data = if visibility == VoxelVisibility::Translucent {
+                    &mut transparent
+                } else {
+                    &mut opaque
+                };
+                let quad_indices = face.quad_mesh_indices(data.positions.len() as u32);
+
+                // split along the brighter diagonal so the occlusion gradient stays symmetric
+                if ao[0] + ao[3] > ao[1] + ao[2] {
+                    data.indices.extend_from_slice(&flip_diagonal(quad_indices));
+                } else {
+                    data.indices.extend_from_slice(&quad_indices);
+                }
+
+                for position in face.quad_mesh_positions(&quad, 1.0) {
+                    data.positions.push(self.chunk_position(position));
+                }
+
+                data.normals.extend_from_slice(&face.quad_mesh_normals());
+
+                let material = materials.get_from_id(voxel.id);
+                let [r, g, b, a] = material.rgba();
+                // blocks giving off light are at least as bright as their own light
+                let [light_r, light_g, light_b] = light[side].max(material.emission()).brightness();
+
+                for level in ao {
+                    let shade = AO_CURVE[level as usize];
+
+                    data.colors.push([
+                        r * light_r * shade,
+                        g * light_g * shade,
+                        b * light_b * shade,
+                        a,
+                    ]);
+                }
+            }
+        }
+
+        (opaque, transparent)
+    }
+
+    /// Merged faces between the voxels matching `solid` and the rest, whether they are seen or
+    /// not, so the stone under opaque lava is still stood on.
+    fn collision_mesh(&self, solid: impl Fn(Voxel) -> bool) -> CollisionMesh {
+        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
+        let voxels = self
+            .voxels
+            .iter()
+            .map(|voxel| SolidVoxel(solid(*voxel)))
+            .collect::<Vec<_>>();
+        let mut buffer = GreedyQuadsBuffer::new(voxels.len());
+
+        block_mesh::greedy_quads(
+            &voxels,
+            &self.shape,
+            [0; 3],
+            (self.interior_size() + 1).to_array(),
+            &faces,
+            &mut buffer,
+        );
+
+        let mut collision = CollisionMesh::default();
+
+        for (group, face) in buffer.quads.groups.into_iter().zip(faces) {
+            for quad in group.into_iter() {
+                let positions = face
+                    .quad_mesh_positions(&quad, 1.0)
+                    .map(|position| self.chunk_position(position));
+
+                collision.extend(&positions, &face.quad_mesh_indices(0));
+            }
+        }
+
+        collision
+    }
+}
+
+/// Vertex attributes of a chunk mesh, before they are handed to bevy.
+#[derive(Default)]
+struct MeshData {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl MeshData {
+    fn append(&mut self, mut other: MeshData) {
+        let offset = self.positions.len() as u32;
+
+        self.positions.append(&mut other.positions);
+        self.normals.append(&mut other.normals);
+        self.colors.append(&mut other.colors);
+        self.indices
+            .extend(other.indices.into_iter().map(|index| index + offset));
+    }
+
+    fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+        for (key, value) in [
+            (
+                Mesh::ATTRIBUTE_POSITION,
+                VertexAttributeValues::Float32x3(self.positions),
+            ),
+            (
+                Mesh::ATTRIBUTE_NORMAL,
+                VertexAttributeValues::Float32x3(self.normals),
+            ),
+            (
+                Mesh::ATTRIBUTE_COLOR,
+                VertexAttributeValues::Float32x4(self.colors),
+            ),
+        ] {
+            mesh.insert_attribute(key, value);
+        }
+
+        mesh.set_indices(Some(Indices::U32(self.indices)));
+        mesh
+    }
+}
+
+/// Re-triangulates a quad along its other diagonal, keeping the winding order.
+fn flip_diagonal(indices: [u32; 6]) -> [u32; 6] {
+    let start = indices[0];
+
+    if indices[1] == start + 1 {
+        [start, start + 1, start + 3, start, start + 3, start + 2]
+    } else {
+        [start, start + 3, start + 1, start, start + 2, start + 3]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{
+        prelude::Mesh,
+        render::mesh::VertexAttributeValues,
+        tasks::{AsyncComputeTaskPool, TaskPool},
+    };
+    use ndshape::ConstShape;
+
+    use super::{MeshingJobs, MeshingMode, AO_CURVE};
+    use crate::{
+        chunk::{
+            container::{ChunkKey, Chunks, DomainChunk},
+            density::DensityStorage,
+            storage::ChunkStorage,
+            ChunkShape, X_SIZE_U32, Y_SIZE_U32, Z_SIZE_U32,
+        },
+        material::Materials,
+    };
+
+    #[test]
+    pub fn padded_view_test() {
+        let mut chunks = Chunks::default();
+
+        chunks
+            .get_domain_at_mut([0, 0, 0])
+            .set_block([3, Y_SIZE_U32 - 1, 4], 2);
+        chunks.get_domain_at_mut([0, 1, 0]).set_block([3, 0, 4], 1);
+        chunks.get_domain_at_mut([1, 1, 0]).set_block([0, 0, 4], 4);
+
+        let view = chunks.padded_view(ChunkKey::ZERO);
+
+        assert_eq!(view.get([3, Y_SIZE_U32 - 1, 4]).id, 2);
+        assert_eq!(view.get([3, Y_SIZE_U32, 4]).id, 1);
+        assert_eq!(view.get([3, Y_SIZE_U32, 5]).id, 0);
+    }
+
+    #[test]
+    pub fn neighbour_culling_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+
+        chunks
+            .get_domain_at_mut([0, 0, 0])
+            .override_blocks(ChunkStorage::Uniform(2));
+
+        // a lone solid chunk is a single merged quad per side
+        let mesh = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Blocky)
+            .opaque;
+        assert_eq!(mesh.count_vertices(), 6 * 4);
+
+        chunks
+            .get_domain_at_mut([1, 0, 0])
+            .override_blocks(ChunkStorage::Uniform(2));
+        chunks
+            .get_domain_at_mut([0, -1, 0])
+            .override_blocks(ChunkStorage::Uniform(1));
+
+        // the faces shared with solid neighbours are culled on both sides
+        let mesh = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Blocky)
+            .opaque;
+        assert_eq!(mesh.count_vertices(), 4 * 4);
+
+        let mesh = chunks
+            .padded_view(ChunkKey::X)
+            .mesh(&materials, MeshingMode::Blocky)
+            .opaque;
+        // the grass below the first chunk occludes the bottom face of this one along their
+        // shared edge, and voxels only merge when all of their faces match, so the sides next to
+        // that edge split off strips of quads as well
+        assert_eq!(mesh.count_vertices(), 12 * 4);
+    }
+
+    #[test]
+    pub fn ambient_occlusion_test() {
+        let mut chunks = Chunks::default();
+        let chunk = chunks.get_domain_at_mut([0, 0, 0]);
+
+        // a 3x3 floor with a single block on top of its centre
+        for x in 4..7 {
+            for z in 4..7 {
+                chunk.set_block([x, 4, z], 2);
+            }
+        }
+
+        chunk.set_block([5, 5, 5], 2);
+
+        let materials = Materials::default();
+        let stone = materials.get_from_id(2).color[0];
+        let view = chunks.padded_view(ChunkKey::ZERO);
+        let mesh = view.mesh(&materials, MeshingMode::Blocky).opaque;
+
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => unreachable!(),
+        };
+        let colors = match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(colors)) => colors,
+            _ => unreachable!(),
+        };
+
+        let top_of_floor = |position: &[f32; 3]| position[1] == 5.0;
+        let brightness = positions
+            .iter()
+            .zip(colors)
+            .filter(|(position, _)| top_of_floor(position))
+            .map(|(position, color)| (*position, color[3] * color[0] / stone))
+            .collect::<Vec<_>>();
+
+        // vertices touching the raised block are occluded, the outer corners are not
+        for (position, light) in brightness {
+            let touches_block =
+                (5.0..=6.0).contains(&position[0]) && (5.0..=6.0).contains(&position[2]);
+
+            if touches_block {
+                assert!(light < AO_CURVE[3], "{position:?} should be occluded");
+            } else if [4.0, 7.0].contains(&position[0]) && [4.0, 7.0].contains(&position[2]) {
+                assert_eq!(light, AO_CURVE[3], "{position:?} should be unoccluded");
+            }
+        }
+
+        // the occluded ring around the block can't be merged into a single quad with the rest
+        let top_quads = positions
+            .iter()
+            .filter(|position| top_of_floor(position))
+            .count()
+            / 4;
+        assert!(top_quads > 1);
+    }
+
+    #[test]
+    pub fn meshing_jobs_test() {
+        AsyncComputeTaskPool::init(TaskPool::new);
+
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+        let mut jobs = MeshingJobs::default();
+
+        chunks
+            .get_domain_at_mut([0, 0, 0])
+            .override_blocks(ChunkStorage::Uniform(2));
+
+        jobs.spawn(
+            ChunkKey::ZERO,
+            1,
+            chunks.padded_view(ChunkKey::ZERO),
+            materials.clone(),
+            MeshingMode::Blocky,
+        );
+        // a newer snapshot replaces the job still running for the same chunk
+        jobs.spawn(
+            ChunkKey::ZERO,
+            2,
+            chunks.padded_view(ChunkKey::ZERO),
+            materials.clone(),
+            MeshingMode::Blocky,
+        );
+        jobs.spawn(
+            ChunkKey::X,
+            1,
+            chunks.padded_view(ChunkKey::X),
+            materials,
+            MeshingMode::Blocky,
+        );
+        jobs.cancel(ChunkKey::X);
+
+        assert_eq!(jobs.len(), 1);
+
+        let mut finished = Vec::new();
+
+        while finished.is_empty() {
+            finished = jobs.pull_finished();
+        }
+
+        let (key, revision, mesh) = finished.pop().unwrap();
+
+        assert_eq!((key, revision), (ChunkKey::ZERO, 2));
+        assert_eq!(mesh.opaque.count_vertices(), 6 * 4);
+        assert_eq!(mesh.transparent.count_vertices(), 0);
+        assert!(jobs.is_empty());
+    }
+
+    #[test]
+    pub fn smooth_seam_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+
+        // a slope of smooth stone running across the border between two chunks
+        for key in [[0, 0, 0], [1, 0, 0]] {
+            let chunk = chunks.get_domain_at_mut(key);
+
+            for x in 0..X_SIZE_U32 {
+                for z in 0..Z_SIZE_U32 {
+                    for y in 0..(4 + (key[0] as u32 * X_SIZE_U32 + x) / 8) {
+                        chunk.set_block([x, y, z], 2);
+                    }
+                }
+            }
+        }
+
+        let positions = |key: ChunkKey| {
+            let mesh = chunks
+                .padded_view(key)
+                .mesh(&materials, MeshingMode::PerMaterial)
+                .opaque;
+
+            match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+                Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+                _ => unreachable!(),
+            }
+        };
+
+        let (left, right) = (positions(ChunkKey::ZERO), positions(ChunkKey::X));
+
+        assert!(!left.is_empty());
+        // nothing is meshed as cubes, so no vertex sits on a whole block corner on every axis
+        assert!(left
+            .iter()
+            .all(|position| position.iter().any(|axis| axis.fract() != 0.0)));
+
+        // both chunks place the vertices of the cubes spanning their shared border identically
+        let border = |positions: Vec<[f32; 3]>, offset: f32| {
+            let mut border = positions
+                .into_iter()
+                .map(|[x, y, z]| [x + offset, y, z])
+                .filter(|[x, _, _]| (31.0..32.0).contains(x))
+                .map(|position| position.map(|axis| (axis * 1000.0).round() as i32))
+                .collect::<Vec<_>>();
+
+            border.sort();
+            border.dedup();
+            border
+        };
+
+        let left_border = border(left, 0.0);
+
+        assert!(!left_border.is_empty());
+        assert_eq!(left_border, border(right, X_SIZE_U32 as f32));
+    }
+
+    #[test]
+    pub fn smooth_density_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+        let chunk = chunks.get_domain_at_mut([0, 0, 0]);
+        let densities = (0..ChunkShape::SIZE)
+            .map(|i| ChunkShape::delinearize(i)[1] as f32 - 10.25)
+            .collect::<Vec<_>>();
+
+        // flat ground whose surface lies a quarter block above its top voxels, with a block broken
+        // out of it later
+        for i in 0..ChunkShape::SIZE {
+            if densities[i as usize] <= 0.0 {
+                chunk.set_block(ChunkShape::delinearize(i), 2);
+            }
+        }
+
+        chunk.set_block([16, 10, 16], 0);
+        chunk.override_density(DensityStorage::from_densities(&densities));
+
+        let mesh = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Smooth)
+            .opaque;
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            unreachable!();
+        };
+        let top = |range: std::ops::Range<f32>| {
+            positions
+                .iter()
+                .filter(move |[x, y, z]| *y > 5.0 && range.contains(x) && range.contains(z))
+        };
+
+        // the surface follows the density instead of the middle between two blocks
+        assert!(top(2.0..12.0).count() > 0);
+        assert!(top(2.0..12.0).all(|[_, y, _]| (y - 10.25).abs() < 0.01));
+
+        // the broken block disagrees with the density and leaves a hole anyway
+        assert!(top(15.0..18.0).any(|[_, y, _]| *y < 10.0));
+    }
+
+    #[test]
+    pub fn lod_seam_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+
+        for key in [[0, 0, 0], [1, 0, 0]] {
+            chunks
+                .get_domain_at_mut(key)
+                .override_blocks(ChunkStorage::Uniform(2));
+        }
+
+        chunks.get_domain_at_mut([1, 0, 0]).lod = 2;
+
+        // the faces between different levels of detail are kept on both sides
+        for key in [ChunkKey::ZERO, ChunkKey::X] {
+            let mesh = chunks
+                .padded_view(key)
+                .mesh(&materials, MeshingMode::Blocky)
+                .opaque;
+
+            assert_eq!(mesh.count_vertices(), 6 * 4);
+        }
+
+        // coarse voxels still span the whole chunk
+        let view = chunks.padded_view(ChunkKey::X);
+        let mesh = view.mesh(&materials, MeshingMode::Blocky).opaque;
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => unreachable!(),
+        };
+
+        assert_eq!(view.get([7, 7, 7]).id, 2);
+        assert!(positions
+            .iter()
+            .flatten()
+            .all(|axis| [0.0, X_SIZE_U32 as f32].contains(axis)));
+
+        chunks.get_domain_at_mut([1, 0, 0]).lod = 0;
+
+        let mesh = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Blocky)
+            .opaque;
+
+        assert_eq!(mesh.count_vertices(), 5 * 4);
+    }
+
+    #[test]
+    pub fn lava_collision_test() {
+        let materials = Materials::default();
+        let lava = materials.id_of("lava").unwrap();
+        let mut chunks = Chunks::default();
+        let chunk = chunks.get_domain_at_mut([0, 0, 0]);
+
+        // a stone floor up to y = 4, under a layer of lava
+        for x in 0..X_SIZE_U32 {
+            for z in 0..Z_SIZE_U32 {
+                for y in 0..5 {
+                    chunk.set_block([x, y, z], 2);
+                }
+
+                chunk.set_block([x, 5, z], lava);
+            }
+        }
+
+        for mode in [
+            MeshingMode::Blocky,
+            MeshingMode::Smooth,
+            MeshingMode::PerMaterial,
+        ] {
+            let collision = chunks
+                .padded_view(ChunkKey::ZERO)
+                .mesh(&materials, mode)
+                .collision;
+
+            // the floor under the lava is there to stand on, the lava itself is walked through
+            assert!(!collision.is_empty(), "{mode:?}");
+            assert!(
+                collision.vertices.iter().all(|vertex| vertex.y <= 5.0),
+                "{mode:?}"
+            );
+            assert!(
+                collision.vertices.iter().any(|vertex| vertex.y >= 4.0),
+                "{mode:?}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn transparent_mesh_test() {
+        let materials = Materials::default();
+        let mut chunks = Chunks::default();
+        let chunk = chunks.get_domain_at_mut([0, 0, 0]);
+
+        // a pool of water two blocks deep on a stone floor, with a pane of glass in it
+        for x in 4..8 {
+            for z in 4..8 {
+                chunk.set_block([x, 4, z], 2);
+                chunk.set_block([x, 5, z], 3);
+                chunk.set_block([x, 6, z], 3);
+            }
+        }
+
+        chunk.set_block([5, 6, 5], 9);
+
+        let mesh = chunks
+            .padded_view(ChunkKey::ZERO)
+            .mesh(&materials, MeshingMode::Blocky);
+
+        // the stone floor is seen through the water, so its top stays meshed
+        let opaque = match mesh.opaque.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => unreachable!(),
+        };
+
+        assert!(opaque.iter().any(|position| position[1] == 5.0));
+
+        // only the outside of the water and the glass is meshed, nothing between them
+        let transparent = match mesh.transparent.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => positions,
+            _ => unreachable!(),
+        };
+        let colors = match mesh.transparent.attribute(Mesh::ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x4(colors)) => colors,
+            _ => unreachable!(),
+        };
+
+        assert!(!transparent.is_empty());
+        assert!(colors.iter().all(|color| color[3] < 1.0));
+        assert!(transparent
+            .iter()
+            .all(|[x, y, z]| [4.0, 8.0].contains(x) || [4.0, 8.0].contains(z) || *y == 7.0));
+    }
+}
@@ -0,0 +1,108 @@
+use bevy::prelude::Vec3;
+use fast_surface_nets::{surface_nets, SurfaceNetsBuffer};
+use ndshape::Shape;
+
+use crate::{chunk::voxel::Voxel, material::Materials};
+
+use super::{MeshData, PaddedChunk};
+
+/// Smallest distance a voxel keeps to the surface, so it never lies exactly on it.
+const MIN_DENSITY: f32 = 1.0 / 128.0;
+
+/// Offsets of the eight corners of a surface nets cube from its minimum corner.
+const CUBE_CORNERS: [[u32; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+impl PaddedChunk {
+    /// Signed distance of every padded voxel, negative inside the voxels matching `smooth`.
+    ///
+    /// Voxels keep the density their chunk was generated with, unless it puts them on the wrong
+    /// side of the surface, like blocks the player placed or broke since, or there is none.
+    /// Those count as a whole block in or out.
+    pub fn density(&self, smooth: impl Fn(Voxel) -> bool) -> Vec<f32> {
+        self.voxels
+            .iter()
+            .zip(&self.density)
+            .map(|(voxel, density)| match (smooth(*voxel), *density) {
+                // a surface right on the voxel still has to stay on the right side of it
+                (true, Some(density)) if density <= 0.0 => density.min(-MIN_DENSITY),
+                (false, Some(density)) if density >= 0.0 => density.max(MIN_DENSITY),
+                (true, _) => -1.0,
+                (false, _) => 1.0,
+            })
+            .collect()
+    }
+
+    /// Meshes the voxels matching `smooth` with surface nets.
+    ///
+    /// Surface nets leave out faces on the positive boundary of the sampled area, so sampling
+    /// from the padding on the negative side up to the padding on the positive side makes the
+    /// meshes of neighbouring chunks meet without gaps or overlap.
+    pub(super) fn smooth_mesh(
+        &self,
+        materials: &Materials,
+        smooth: impl Fn(Voxel) -> bool + Copy,
+    ) -> MeshData {
+        let density = self.density(smooth);
+        let mut buffer = SurfaceNetsBuffer::default();
+
+        surface_nets(
+            &density,
+            &self.shape,
+            [0; 3],
+            (self.interior_size() + 1).to_array(),
+            &mut buffer,
+        );
+
+        // each vertex takes the colour of a smooth voxel on a corner of its cube, lit by the
+        // brightest of the corners outside the surface
+        let colors = buffer
+            .surface_points
+            .iter()
+            .map(|[x, y, z]| {
+                let corners = CUBE_CORNERS
+                    .map(|[cx, cy, cz]| self.shape.linearize([x + cx, y + cy, z + cz]) as usize);
+                let id = corners
+                    .iter()
+                    .map(|index| self.voxels[*index])
+                    .find(|voxel| smooth(*voxel))
+                    .map_or(0, |voxel| voxel.id);
+                let material = materials.get_from_id(id);
+                let [light_r, light_g, light_b] = corners
+                    .iter()
+                    .filter(|index| !smooth(self.voxels[**index]))
+                    .fold(material.emission(), |light, index| {
+                        light.max(self.light[*index])
+                    })
+                    .brightness();
+
+                let [r, g, b, a] = material.rgba();
+
+                [r * light_r, g * light_g, b * light_b, a]
+            })
+            .collect();
+
+        MeshData {
+            positions: buffer
+                .positions
+                .into_iter()
+                .map(|position| self.chunk_position(position))
+                .collect(),
+            normals: buffer
+                .normals
+                .into_iter()
+                .map(|normal| Vec3::from_array(normal).normalize_or_zero().to_array())
+                .collect(),
+            colors,
+            indices: buffer.indices,
+        }
+    }
+}
@@ -1,9 +1,20 @@
-use bevy::prelude::{Entity, IVec2, Mesh};
+use bevy::prelude::{Entity, IVec3};
 use ndshape::{ConstShape, ConstShape2usize, ConstShape3u32};
 
+use self::{density::DensityStorage, light::LightStorage, storage::ChunkStorage};
+
+pub mod collider;
 pub mod container;
+pub mod density;
+pub mod edit;
+pub mod generation;
+pub mod light;
+pub mod lod;
 pub mod meshing;
+pub mod pending;
 pub mod plugin;
+pub mod raycast;
+pub mod storage;
 pub mod voxel;
 
 pub const X_SIZE: usize = 32;
@@ -21,42 +32,75 @@ pub const HEIGHT_SIZE: usize = NoiseShape::SIZE;
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
-    blocks: [u8; ChunkShape::SIZE as usize],
-    pub world_pos: IVec2,
-    pub mesh: Option<Mesh>,
+    blocks: ChunkStorage,
+    light: LightStorage,
+    density: DensityStorage,
+    pub position: IVec3,
+    pub world_pos: IVec3,
     pub entity: Option<Entity>,
+    /// Draws the translucent faces of the chunk, like water.
+    pub transparent_entity: Option<Entity>,
+    /// Holds the chunk's terrain collider while it is within the
+    /// [`PhysicsRadius`](collider::PhysicsRadius).
+    pub collider: Option<Entity>,
     pub dirty: bool,
+    /// Level of detail the chunk is meshed at, each level halves the resolution.
+    pub lod: u8,
+    /// Bumped every time the chunk is marked dirty, meshes built from an older revision are stale.
+    pub revision: u64,
+    /// Whether blocks were changed after the chunk got generated.
+    pub edited: bool,
+    /// Whether blocks were changed since the chunk was last saved or loaded from the save.
+    pub unsaved: bool,
 }
 
 impl Chunk {
-    pub fn new(x: i32, z: i32) -> Self {
+    pub fn new(position: IVec3) -> Self {
         Self {
-            mesh: None,
             entity: None,
-            blocks: [0; ChunkShape::SIZE as usize],
-            world_pos: IVec2::new(x, z),
+            transparent_entity: None,
+            collider: None,
+            blocks: ChunkStorage::default(),
+            light: LightStorage::default(),
+            density: DensityStorage::default(),
+            position,
+            world_pos: position * IVec3::new(X_SIZE as i32, Y_SIZE as i32, Z_SIZE as i32),
             dirty: true,
+            lod: 0,
+            revision: 0,
+            edited: false,
+            unsaved: false,
         }
     }
 
-    pub fn override_blocks(&mut self, blocks: [u8; ChunkShape::SIZE as usize]) {
-        self.blocks = blocks
+    pub fn override_blocks(&mut self, blocks: impl Into<ChunkStorage>) {
+        self.blocks = blocks.into();
     }
 
     pub fn set_block_domain(&mut self, position: usize, id: u8) {
-        self.blocks[position] = id;
+        self.blocks.set(position, id);
     }
 
     pub fn get_block_domain(&self, position: usize) -> u8 {
-        self.blocks[position]
+        self.blocks.get(position)
     }
 
     pub fn set_block(&mut self, positions: [u32; 3], id: u8) {
-        self.blocks[ChunkShape::linearize(positions) as usize] = id;
+        self.blocks
+            .set(ChunkShape::linearize(positions) as usize, id);
     }
 
     pub fn get_block(&self, positions: [u32; 3]) -> u8 {
-        self.blocks[ChunkShape::linearize(positions) as usize]
+        self.blocks.get(ChunkShape::linearize(positions) as usize)
+    }
+
+    pub fn blocks(&self) -> &ChunkStorage {
+        &self.blocks
+    }
+
+    /// Bytes used by the block storage of this chunk.
+    pub fn memory_usage(&self) -> usize {
+        self.blocks.memory_usage()
     }
 }
 
@@ -64,8 +108,4 @@ impl PartialEq for Chunk {
     fn eq(&self, other: &Self) -> bool {
         self.world_pos == other.world_pos
     }
-
-    fn ne(&self, other: &Self) -> bool {
-        self.world_pos != other.world_pos
-    }
 }
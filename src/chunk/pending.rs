@@ -0,0 +1,97 @@
+use bevy::{
+    prelude::{IVec3, Resource},
+    utils::HashMap,
+};
+use ndshape::ConstShape;
+
+use super::{
+    container::{ChunkKey, Chunks},
+    storage::ChunkStorage,
+    ChunkShape,
+};
+
+/// Blocks world generation placed into chunks other than the one being generated, like the
+/// leaves of a tree growing over a chunk border, keyed by the chunk they belong to.
+///
+/// Writes stay buffered after they were applied, so a chunk that is generated again gets them
+/// again, until [`PendingWrites::retain`] drops them.
+#[derive(Resource, Default, Debug)]
+pub struct PendingWrites {
+    chunks: HashMap<ChunkKey, HashMap<u32, u8>>,
+}
+
+impl PendingWrites {
+    /// Buffers `block` at the world block `position`, returns the key of its chunk.
+    pub fn push(&mut self, position: IVec3, block: u8) -> ChunkKey {
+        let (key, local) = Chunks::split_world_position(position);
+
+        self.chunks
+            .entry(key)
+            .or_default()
+            .insert(ChunkShape::linearize(local), block);
+
+        key
+    }
+
+    pub fn contains(&self, key: ChunkKey) -> bool {
+        self.chunks.contains_key(&key)
+    }
+
+    /// Writes every block buffered for the chunk at `key` into its `blocks`.
+    pub fn apply(&self, key: ChunkKey, blocks: &mut ChunkStorage) {
+        for (index, block) in self.chunks.get(&key).into_iter().flatten() {
+            blocks.set(*index as usize, *block);
+        }
+    }
+
+    /// Drops the writes of every chunk whose key doesn't match `keep`.
+    pub fn retain(&mut self, keep: impl Fn(ChunkKey) -> bool) {
+        self.chunks.retain(|key, _| keep(*key));
+    }
+
+    /// Number of chunks with buffered writes.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::IVec3;
+
+    use super::PendingWrites;
+    use crate::chunk::{storage::ChunkStorage, ChunkShape};
+    use ndshape::ConstShape;
+
+    #[test]
+    pub fn pending_writes_test() {
+        let mut pending = PendingWrites::default();
+
+        assert_eq!(pending.push(IVec3::new(-1, 40, 3), 7), IVec3::new(-1, 1, 0));
+        assert_eq!(pending.push(IVec3::new(-1, 41, 3), 8), IVec3::new(-1, 1, 0));
+        pending.push(IVec3::new(5, 0, 0), 7);
+
+        let mut blocks = ChunkStorage::default();
+
+        pending.apply(IVec3::new(-1, 1, 0), &mut blocks);
+
+        assert_eq!(blocks.get(ChunkShape::linearize([31, 8, 3]) as usize), 7);
+        assert_eq!(blocks.get(ChunkShape::linearize([31, 9, 3]) as usize), 8);
+        assert_eq!(blocks.palette_len(), 3);
+
+        // applying again gives a regenerated chunk the same blocks
+        let mut regenerated = ChunkStorage::default();
+
+        pending.apply(IVec3::new(-1, 1, 0), &mut regenerated);
+        assert_eq!(regenerated.to_vec(), blocks.to_vec());
+
+        pending.retain(|key| key == IVec3::ZERO);
+
+        assert_eq!(pending.len(), 1);
+        assert!(!pending.contains(IVec3::new(-1, 1, 0)));
+    }
+}
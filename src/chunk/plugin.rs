@@ -1,24 +1,48 @@
+use std::time::{Duration, Instant};
+
 use bevy::{
     ecs::schedule::ShouldRun,
     prelude::{
-        Assets, Commands, Mesh, PbrBundle, ResMut, StageLabel, StandardMaterial, State,
-        SystemLabel, SystemSet, Transform, Vec3, Visibility,
+        AlphaMode, Assets, Commands, Local, Mesh, PbrBundle, Res, ResMut, Resource, StageLabel,
+        StandardMaterial, State, SystemLabel, SystemSet, Transform, TransformBundle, Vec3,
+        Visibility,
     },
-    scene::SceneBundle,
 };
+use bevy_rapier3d::prelude::{Collider, RigidBody};
 
-use crate::chunk::container::DomainChunk;
+use crate::{chunk::container::DomainChunk, material::Materials, world::WorldStorage};
 
-use super::container::{self, loaded::LoadedChunks, Chunks};
+use super::{
+    collider::PhysicsRadius,
+    container::{self, loaded::LoadedChunks, ChunkKey, Chunks},
+    meshing::{MeshingJobs, MeshingMode},
+    pending::PendingWrites,
+};
 
 pub struct ChunkPlugin;
 pub struct ChunkStage;
 
+/// Time [`ChunkPlugin::render_blocks`] may spend installing chunks and starting meshing jobs
+/// each frame.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ChunkUpdateBudget(pub Duration);
+
+impl Default for ChunkUpdateBudget {
+    fn default() -> Self {
+        Self(Duration::from_millis(4))
+    }
+}
+
 impl ChunkPlugin {
-    pub fn render_queue_check() -> ShouldRun {
-        return container::get_update_queue().has_queue().into();
+    pub fn render_queue_check(chunks: Res<Chunks>, mesh_jobs: Res<MeshingJobs>) -> ShouldRun {
+        (container::get_update_queue().has_queue()
+            || chunks.has_dirty()
+            || chunks.has_light_updates()
+            || !mesh_jobs.is_empty())
+        .into()
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn render_blocks(
         mut commands: Commands,
         mut chunks: ResMut<Chunks>,
@@ -26,40 +50,116 @@ impl ChunkPlugin {
         mut bevy_materials: ResMut<Assets<StandardMaterial>>,
         mut state: ResMut<State<ChunkLoadState>>,
         mut loaded_chunks: ResMut<LoadedChunks>,
+        world_storage: Res<WorldStorage>,
+        materials: Res<Materials>,
+        budget: Res<ChunkUpdateBudget>,
+        mut mesh_jobs: ResMut<MeshingJobs>,
+        meshing_mode: Res<MeshingMode>,
+        pending: Res<PendingWrites>,
+        physics_radius: Res<PhysicsRadius>,
     ) {
         let mut outer_most_x = 0;
+        let unloaded = loaded_chunks.pull_unload();
+
+        for key in &unloaded {
+            mesh_jobs.cancel(*key);
+        }
 
-        loaded_chunks.pull_unload().iter().for_each(|chunk| {
-            let [x, z] = Chunks::delinearize(*chunk);
-            let chunk = chunks.get_domain_at_mut([x, z]);
+        for entity in chunks.unload(unloaded, &world_storage) {
+            commands.entity(entity).despawn();
+        }
 
-            if let Some(entity) = chunk.entity {
-                let entity = commands.get_entity(entity);
+        let deadline = Instant::now() + budget.0;
+        let mut queue = container::get_update_queue();
 
-                if let Some(mut entity) = entity {
-                    entity
-                        .insert(SceneBundle {
-                            visibility: Visibility { is_visible: false },
-                            ..Default::default()
-                        })
-                        .remove::<PbrBundle>();
+        while Instant::now() < deadline {
+            let Some((key, mut blocks, density)) = queue.pop() else {
+                break;
+            };
+
+            let chunk = chunks.get_domain_at_mut(key.to_array());
+
+            if loaded_chunks.is_chunk_loaded(chunk) && !chunk.dirty {
+                continue;
+            }
+
+            loaded_chunks.add_rendered_chunk(key);
+
+            // regenerating would throw away what was placed or broken in the chunk
+            if !chunk.edited {
+                // blocks of features anchored in neighbouring chunks
+                pending.apply(key, &mut blocks);
+                chunk.override_blocks(blocks);
+            }
+
+            // edited blocks that disagree with it are meshed as whole blocks
+            chunk.override_density(density);
+
+            let lod = chunks.lod_at(key);
+
+            chunks.get_domain_at_mut(key.to_array()).lod = lod;
+
+            chunks.mark_dirty(key);
+            chunks.queue_light(key);
+
+            // neighbours meshed before this chunk existed are culled and occluded against it now
+            for neighbour in container::neighbours(key) {
+                if loaded_chunks.is_chunk_id_loaded(&neighbour) {
+                    chunks.mark_dirty(neighbour);
                 }
             }
-        });
+        }
 
-        for (pos, blocks) in container::get_update_queue().pull(0..2) {
-            let [x, z] = Chunks::delinearize(pos);
-            let chunk = chunks.get_domain_at_mut([x, z]);
+        // light the installed chunks before meshing them, so they don't get meshed twice
+        chunks.update_light(&materials, &loaded_chunks, deadline);
 
-            if loaded_chunks.is_chunk_loaded(chunk) && !chunk.dirty {
+        let mut dirty = chunks.pull_dirty().into_iter().collect::<Vec<_>>();
+        dirty.sort_by(|a, b| queue.priority(*a).total_cmp(&queue.priority(*b)));
+        drop(queue);
+
+        for (spawned, key) in dirty.into_iter().enumerate() {
+            // always mesh at least one chunk so a tight budget can't stall updates entirely
+            if spawned > 0 && Instant::now() >= deadline {
+                chunks.mark_dirty(key);
                 continue;
             }
 
-            loaded_chunks.add_rendered_chunk(pos as i32);
-            chunk.override_blocks(blocks);
+            // edits only reach loaded chunks, but their neighbours may have been unloaded since
+            if !loaded_chunks.is_chunk_id_loaded(&key) {
+                continue;
+            }
+
+            // lighting ran out of budget before getting to the chunk
+            if chunks.is_light_queued(key) {
+                chunks.mark_dirty(key);
+                continue;
+            }
+
+            let Some(revision) = chunks.get(key).map(|chunk| chunk.revision) else {
+                continue;
+            };
+
+            mesh_jobs.spawn(
+                key,
+                revision,
+                chunks.padded_view(key),
+                materials.clone(),
+                *meshing_mode,
+            );
+        }
 
-            let mesh = chunk.get_mesh();
-            let handle = meshes.add(mesh);
+        for (key, revision, mesh) in mesh_jobs.pull_finished() {
+            let in_physics_radius = chunks.in_physics_radius(key, *physics_radius);
+            let Some(chunk) = chunks.get_mut(key) else {
+                continue;
+            };
+
+            // the chunk changed while it was being meshed, a newer job is already running
+            if chunk.revision != revision {
+                continue;
+            }
+
+            chunk.dirty = false;
 
             if chunk.world_pos.x > outer_most_x {
                 outer_most_x = chunk.world_pos.x;
@@ -67,38 +167,107 @@ impl ChunkPlugin {
 
             const SCALE: f32 = 1.0;
 
-            if let None = chunk.entity {
-                chunk.entity = Some(commands.spawn_empty().id());
-            }
+            let transform = Transform::from_translation(chunk.world_pos.as_vec3() * SCALE)
+                .with_scale(Vec3::new(SCALE, SCALE, SCALE));
 
-            let entity = chunk.entity.unwrap();
-            let mut commands = commands.entity(entity);
+            // chunks without solid faces keep an empty collider entity, so
+            // `update_colliders` can tell they were already meshed for physics
+            if in_physics_radius {
+                let entity = *chunk.collider.get_or_insert_with(|| {
+                    commands
+                        .spawn((RigidBody::Fixed, TransformBundle::from_transform(transform)))
+                        .id()
+                });
+
+                match mesh.collision.into_collider() {
+                    Some(collider) => commands.entity(entity).insert(collider),
+                    None => commands.entity(entity).remove::<Collider>(),
+                };
+            } else if let Some(collider) = chunk.collider.take() {
+                commands.entity(collider).despawn();
+            }
 
-            commands
-                .remove::<Visibility>()
-                .remove::<PbrBundle>() // remove previous pbr bundle (will this unrender it? idk)
-                .insert(PbrBundle {
-                    mesh: handle,
-                    material: bevy_materials.add(StandardMaterial {
+            for (mesh, entity, material) in [
+                (
+                    mesh.opaque,
+                    &mut chunk.entity,
+                    StandardMaterial {
                         perceptual_roughness: 0.47,
                         ..Default::default()
-                    }),
-                    transform: Transform::from_translation(Vec3::new(
-                        chunk.world_pos.x as f32 * SCALE,
-                        0.0,
-                        chunk.world_pos.y as f32 * SCALE,
-                    ))
-                    .with_scale(Vec3::new(SCALE, SCALE, SCALE)),
-                    ..Default::default()
-                });
+                    },
+                ),
+                (
+                    mesh.transparent,
+                    &mut chunk.transparent_entity,
+                    // water is seen from below as well when diving
+                    StandardMaterial {
+                        perceptual_roughness: 0.1,
+                        alpha_mode: AlphaMode::Blend,
+                        cull_mode: None,
+                        double_sided: true,
+                        ..Default::default()
+                    },
+                ),
+            ] {
+                if mesh.count_vertices() == 0 {
+                    if let Some(entity) = entity {
+                        commands.entity(*entity).remove::<PbrBundle>();
+                    }
 
-            chunk.dirty = false;
+                    continue;
+                }
+
+                let entity = *entity.get_or_insert_with(|| commands.spawn_empty().id());
+
+                commands
+                    .entity(entity)
+                    .remove::<Visibility>()
+                    .remove::<PbrBundle>() // remove previous pbr bundle (will this unrender it? idk)
+                    .insert(PbrBundle {
+                        mesh: meshes.add(mesh),
+                        material: bevy_materials.add(material),
+                        transform,
+                        ..Default::default()
+                    });
+            }
         }
 
         if let ChunkLoadState::Render = state.current() {
             state.overwrite_set(ChunkLoadState::Wait).unwrap();
         }
     }
+
+    /// Drops the colliders of chunks that left the [`PhysicsRadius`] and re-meshes the chunks
+    /// that entered it, so they get one.
+    pub fn update_colliders(
+        mut commands: Commands,
+        mut chunks: ResMut<Chunks>,
+        loaded_chunks: Res<LoadedChunks>,
+        physics_radius: Res<PhysicsRadius>,
+        mut center: Local<Option<ChunkKey>>,
+    ) {
+        if *center == Some(chunks.lod_center()) && !physics_radius.is_changed() {
+            return;
+        }
+
+        *center = Some(chunks.lod_center());
+
+        for key in loaded_chunks.pull_loaded() {
+            let inside = chunks.in_physics_radius(key, *physics_radius);
+            let Some(chunk) = chunks.get_mut(key) else {
+                continue;
+            };
+
+            match (chunk.collider, inside) {
+                (Some(collider), false) => {
+                    commands.entity(collider).despawn();
+                    chunk.collider = None;
+                }
+                (None, true) => chunks.mark_dirty(key),
+                _ => {}
+            }
+        }
+    }
 }
 
 #[derive(StageLabel)]
@@ -115,6 +284,11 @@ impl bevy::app::Plugin for ChunkPlugin {
         // app.update();
         app.insert_resource(Chunks::default())
             .insert_resource(LoadedChunks::default())
+            .init_resource::<ChunkUpdateBudget>()
+            .init_resource::<MeshingJobs>()
+            .init_resource::<MeshingMode>()
+            .init_resource::<PhysicsRadius>()
+            .add_system(ChunkPlugin::update_colliders)
             .add_state(ChunkLoadState::Render)
             .add_system_set(
                 SystemSet::on_enter(ChunkLoadState::Render)
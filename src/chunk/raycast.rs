@@ -0,0 +1,141 @@
+use bevy::prelude::{IVec3, Vec3};
+
+use super::container::Chunks;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    /// World position of the block that was hit.
+    pub position: IVec3,
+    /// Normal of the face the ray entered the block through, zero if the ray started inside it.
+    pub normal: IVec3,
+    /// Distance travelled along the ray until it entered the block.
+    pub distance: f32,
+}
+
+impl Chunks {
+    /// Walks the voxel grid along the ray (Amanatides & Woo) and returns the
+    /// first non-air block within `max_distance`.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        let direction = direction.try_normalize()?;
+
+        let mut position = origin.floor().as_ivec3();
+        let mut normal = IVec3::ZERO;
+        let mut distance = 0.0;
+
+        let step = IVec3::from_array(direction.to_array().map(|axis| {
+            if axis > 0.0 {
+                1
+            } else if axis < 0.0 {
+                -1
+            } else {
+                0
+            }
+        }));
+
+        // distance along the ray between two grid planes, per axis
+        let t_delta = direction.abs().recip();
+
+        // distance along the ray to the first grid plane, per axis
+        let mut t_max = Vec3::from_array([0, 1, 2].map(|axis| match step[axis] {
+            1 => (position[axis] as f32 + 1.0 - origin[axis]) * t_delta[axis],
+            -1 => (origin[axis] - position[axis] as f32) * t_delta[axis],
+            _ => f32::INFINITY,
+        }));
+
+        loop {
+            if self.get_block_world(position) != 0 {
+                return Some(RaycastHit {
+                    position,
+                    normal,
+                    distance,
+                });
+            }
+
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z {
+                0
+            } else if t_max.y < t_max.z {
+                1
+            } else {
+                2
+            };
+
+            distance = t_max[axis];
+
+            if distance > max_distance {
+                return None;
+            }
+
+            position[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+
+            normal = IVec3::ZERO;
+            normal[axis] = -step[axis];
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::{IVec3, Vec3};
+
+    use crate::chunk::container::Chunks;
+
+    #[test]
+    pub fn raycast_hit_test() {
+        let mut chunks = Chunks::default();
+
+        chunks.set_block_world(IVec3::new(5, 2, 3), 2);
+        chunks.set_block_world(IVec3::new(-40, -3, -7), 1);
+
+        let hit = chunks
+            .raycast(Vec3::new(0.5, 2.5, 3.5), Vec3::X, 16.0)
+            .unwrap();
+
+        assert_eq!(hit.position, IVec3::new(5, 2, 3));
+        assert_eq!(hit.normal, IVec3::NEG_X);
+        assert!((hit.distance - 4.5).abs() < 1e-5);
+
+        // across chunk borders and below the origin
+        let hit = chunks
+            .raycast(
+                Vec3::new(-30.5, -2.5, -6.5),
+                Vec3::new(-9.0, -0.5, -0.5),
+                16.0,
+            )
+            .unwrap();
+
+        assert_eq!(hit.position, IVec3::new(-40, -3, -7));
+        assert_eq!(hit.normal, IVec3::X);
+
+        let hit = chunks
+            .raycast(Vec3::new(5.5, 10.0, 3.5), Vec3::NEG_Y, 16.0)
+            .unwrap();
+
+        assert_eq!(hit.normal, IVec3::Y);
+        assert!((hit.distance - 7.0).abs() < 1e-5);
+    }
+
+    #[test]
+    pub fn raycast_miss_test() {
+        let mut chunks = Chunks::default();
+
+        chunks.set_block_world(IVec3::new(5, 2, 3), 2);
+
+        assert!(chunks
+            .raycast(Vec3::new(0.5, 2.5, 3.5), Vec3::X, 4.0)
+            .is_none());
+        assert!(chunks
+            .raycast(Vec3::new(0.5, 2.5, 3.5), Vec3::NEG_X, 64.0)
+            .is_none());
+        assert!(chunks
+            .raycast(Vec3::new(0.5, 2.5, 3.5), Vec3::ZERO, 64.0)
+            .is_none());
+
+        let hit = chunks
+            .raycast(Vec3::new(5.5, 2.5, 3.5), Vec3::Y, 4.0)
+            .unwrap();
+
+        assert_eq!(hit.normal, IVec3::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+}
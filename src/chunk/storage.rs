@@ -0,0 +1,466 @@
+use std::mem;
+
+use ndshape::ConstShape;
+
+use super::ChunkShape;
+
+const SIZE: usize = ChunkShape::SIZE as usize;
+
+const UNIFORM_TAG: u8 = 0;
+const PALETTED_TAG: u8 = 1;
+
+/// Block storage of a single chunk.
+///
+/// Chunks made out of a single block type only store that id, every other
+/// chunk stores 1, 2, 4 or 8 bit indices into a per-chunk palette. The index
+/// width grows on demand whenever a new block type gets written.
+#[derive(Debug, Clone)]
+pub enum ChunkStorage {
+    Uniform(u8),
+    Paletted(Box<PalettedStorage>),
+}
+
+#[derive(Debug, Clone)]
+pub struct PalettedStorage {
+    palette: Vec<u8>,
+    counts: Vec<u32>,
+    bits: u8,
+    indices: Vec<u8>,
+}
+
+impl Default for ChunkStorage {
+    fn default() -> Self {
+        Self::Uniform(0)
+    }
+}
+
+impl From<[u8; SIZE]> for ChunkStorage {
+    fn from(blocks: [u8; SIZE]) -> Self {
+        Self::from_blocks(&blocks)
+    }
+}
+
+impl ChunkStorage {
+    pub fn from_blocks(blocks: &[u8]) -> Self {
+        assert_eq!(blocks.len(), SIZE);
+
+        let mut palette = Vec::new();
+        let mut counts = Vec::new();
+        let mut lookup = [None; 256];
+
+        let slots = blocks
+            .iter()
+            .map(|id| {
+                let slot = *lookup[*id as usize].get_or_insert_with(|| {
+                    palette.push(*id);
+                    counts.push(0);
+                    palette.len() - 1
+                });
+
+                counts[slot] += 1;
+                slot as u8
+            })
+            .collect::<Vec<_>>();
+
+        if palette.len() == 1 {
+            return Self::Uniform(palette[0]);
+        }
+
+        let mut storage = PalettedStorage {
+            bits: PalettedStorage::bits_for(palette.len()),
+            palette,
+            counts,
+            indices: Vec::new(),
+        };
+
+        storage.indices = vec![0; SIZE * storage.bits as usize / 8];
+
+        for (index, slot) in slots.into_iter().enumerate() {
+            storage.set_slot(index, slot);
+        }
+
+        Self::Paletted(Box::new(storage))
+    }
+
+    pub fn get(&self, index: usize) -> u8 {
+        match self {
+            Self::Uniform(id) => *id,
+            Self::Paletted(storage) => storage.get(index),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, id: u8) {
+        match self {
+            Self::Uniform(current) => {
+                if *current != id {
+                    let mut storage = PalettedStorage::filled(*current);
+                    storage.set(index, id);
+
+                    *self = Self::Paletted(Box::new(storage));
+                }
+            }
+            Self::Paletted(storage) => {
+                if storage.set(index, id) == SIZE as u32 {
+                    *self = Self::Uniform(id);
+                }
+            }
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        (0..SIZE).map(|index| self.get(index)).collect()
+    }
+
+    /// Serializes the storage, see [`ChunkStorage::from_bytes`] for the layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Uniform(id) => vec![UNIFORM_TAG, *id],
+            Self::Paletted(storage) => {
+                let mut bytes =
+                    Vec::with_capacity(4 + storage.palette.len() + storage.indices.len());
+
+                bytes.push(PALETTED_TAG);
+                bytes.push(storage.bits);
+                bytes.extend_from_slice(&(storage.palette.len() as u16).to_le_bytes());
+                bytes.extend_from_slice(&storage.palette);
+                bytes.extend_from_slice(&storage.indices);
+                bytes
+            }
+        }
+    }
+
+    /// Reads a storage written by [`ChunkStorage::to_bytes`].
+    ///
+    /// Uniform chunks are a `0` tag followed by the block id, paletted chunks are a `1` tag,
+    /// the index width, the palette length as a little endian `u16`, the palette and the packed indices.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match *bytes.first()? {
+            UNIFORM_TAG => Some(Self::Uniform(*bytes.get(1)?)),
+            PALETTED_TAG => {
+                let bits = *bytes.get(1)?;
+                let len = u16::from_le_bytes([*bytes.get(2)?, *bytes.get(3)?]) as usize;
+
+                if ![1, 2, 4, 8].contains(&bits) || len == 0 || len > 1 << bits {
+                    return None;
+                }
+
+                let palette = bytes.get(4..4 + len)?;
+                let indices = bytes.get(4 + len..)?;
+
+                if indices.len() != SIZE * bits as usize / 8 {
+                    return None;
+                }
+
+                let storage = PalettedStorage {
+                    palette: palette.to_vec(),
+                    counts: vec![0; len],
+                    bits,
+                    indices: indices.to_vec(),
+                };
+
+                let blocks = (0..SIZE)
+                    .map(|index| {
+                        storage
+                            .palette
+                            .get(storage.get_slot(index) as usize)
+                            .copied()
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+
+                Some(Self::from_blocks(&blocks))
+            }
+            _ => None,
+        }
+    }
+
+    /// Drops unused palette entries and narrows the index width as far as the
+    /// remaining entries allow.
+    pub fn shrink(&mut self) {
+        if let Self::Paletted(storage) = self {
+            if let Some(id) = storage.shrink() {
+                *self = Self::Uniform(id);
+            }
+        }
+    }
+
+    /// Width of a single palette index in bits, `0` for uniform chunks.
+    pub fn bits_per_block(&self) -> u8 {
+        match self {
+            Self::Uniform(_) => 0,
+            Self::Paletted(storage) => storage.bits,
+        }
+    }
+
+    /// Number of blocks with the given id.
+    pub fn count(&self, id: u8) -> u32 {
+        match self {
+            Self::Uniform(current) => {
+                if *current == id {
+                    SIZE as u32
+                } else {
+                    0
+                }
+            }
+            Self::Paletted(storage) => storage
+                .palette
+                .iter()
+                .position(|entry| *entry == id)
+                .map_or(0, |slot| storage.counts[slot]),
+        }
+    }
+
+    pub fn palette_len(&self) -> usize {
+        match self {
+            Self::Uniform(_) => 1,
+            Self::Paletted(storage) => storage.palette.len(),
+        }
+    }
+
+    /// Bytes used by this storage, including its heap allocations.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>()
+            + match self {
+                Self::Uniform(_) => 0,
+                Self::Paletted(storage) => {
+                    mem::size_of::<PalettedStorage>()
+                        + storage.palette.capacity()
+                        + storage.counts.capacity() * mem::size_of::<u32>()
+                        + storage.indices.capacity()
+                }
+            }
+    }
+}
+
+impl PalettedStorage {
+    fn filled(id: u8) -> Self {
+        Self {
+            palette: vec![id],
+            counts: vec![SIZE as u32],
+            bits: 1,
+            indices: vec![0; SIZE / 8],
+        }
+    }
+
+    fn bits_for(len: usize) -> u8 {
+        match len {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    fn get(&self, index: usize) -> u8 {
+        self.palette[self.get_slot(index) as usize]
+    }
+
+    /// Writes `id` at `index`, returning how many blocks now share that id.
+    fn set(&mut self, index: usize, id: u8) -> u32 {
+        let previous = self.get_slot(index) as usize;
+
+        if self.palette[previous] == id {
+            return self.counts[previous];
+        }
+
+        self.counts[previous] -= 1;
+
+        let slot = self.slot_for(id);
+
+        self.counts[slot] += 1;
+        self.set_slot(index, slot as u8);
+        self.counts[slot]
+    }
+
+    fn slot_for(&mut self, id: u8) -> usize {
+        if let Some(slot) = self.palette.iter().position(|entry| *entry == id) {
+            return slot;
+        }
+
+        if let Some(slot) = self.counts.iter().position(|count| *count == 0) {
+            self.palette[slot] = id;
+            return slot;
+        }
+
+        self.palette.push(id);
+        self.counts.push(0);
+
+        if self.palette.len() > 1 << self.bits {
+            self.resize(self.bits * 2);
+        }
+
+        self.palette.len() - 1
+    }
+
+    fn shrink(&mut self) -> Option<u8> {
+        let mut remap = vec![0u8; self.palette.len()];
+        let mut palette = Vec::new();
+        let mut counts = Vec::new();
+
+        for (slot, (id, count)) in self.palette.iter().zip(&self.counts).enumerate() {
+            if *count > 0 {
+                remap[slot] = palette.len() as u8;
+                palette.push(*id);
+                counts.push(*count);
+            }
+        }
+
+        if palette.len() == 1 {
+            return Some(palette[0]);
+        }
+
+        let slots = (0..SIZE)
+            .map(|index| remap[self.get_slot(index) as usize])
+            .collect::<Vec<_>>();
+
+        self.palette = palette;
+        self.counts = counts;
+        self.bits = Self::bits_for(self.palette.len());
+        self.indices = vec![0; SIZE * self.bits as usize / 8];
+
+        for (index, slot) in slots.into_iter().enumerate() {
+            self.set_slot(index, slot);
+        }
+
+        None
+    }
+
+    fn resize(&mut self, bits: u8) {
+        let slots = (0..SIZE)
+            .map(|index| self.get_slot(index))
+            .collect::<Vec<_>>();
+
+        self.bits = bits;
+        self.indices = vec![0; SIZE * bits as usize / 8];
+
+        for (index, slot) in slots.into_iter().enumerate() {
+            self.set_slot(index, slot);
+        }
+    }
+
+    fn mask(&self) -> u8 {
+        ((1u16 << self.bits) - 1) as u8
+    }
+
+    fn get_slot(&self, index: usize) -> u8 {
+        let per_byte = 8 / self.bits as usize;
+        let shift = (index % per_byte) * self.bits as usize;
+
+        (self.indices[index / per_byte] >> shift) & self.mask()
+    }
+
+    fn set_slot(&mut self, index: usize, slot: u8) {
+        let per_byte = 8 / self.bits as usize;
+        let shift = (index % per_byte) * self.bits as usize;
+        let mask = self.mask();
+        let byte = &mut self.indices[index / per_byte];
+
+        *byte = (*byte & !(mask << shift)) | ((slot & mask) << shift);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChunkStorage, SIZE};
+
+    #[test]
+    pub fn uniform_storage_test() {
+        let storage = ChunkStorage::from_blocks(&[3; SIZE]);
+
+        assert!(matches!(storage, ChunkStorage::Uniform(3)));
+        assert_eq!(storage.get(0), 3);
+        assert_eq!(storage.get(SIZE - 1), 3);
+        assert!(storage.memory_usage() <= 16);
+    }
+
+    #[test]
+    pub fn palette_growth_test() {
+        let mut storage = ChunkStorage::default();
+
+        for (id, bits) in [(1, 1), (2, 2), (3, 2), (4, 4), (15, 4), (16, 8)] {
+            for previous in 1..=id {
+                storage.set(previous as usize * 7, previous);
+            }
+
+            assert_eq!(
+                storage.bits_per_block(),
+                bits,
+                "palette with {id} extra ids"
+            );
+            assert_eq!(storage.palette_len(), id as usize + 1);
+        }
+
+        for id in 1..=16u8 {
+            assert_eq!(storage.get(id as usize * 7), id);
+        }
+
+        assert_eq!(storage.get(1), 0);
+        assert!(storage.memory_usage() < SIZE + 1024);
+    }
+
+    #[test]
+    pub fn palette_round_trip_test() {
+        let blocks = (0..SIZE).map(|i| (i % 37) as u8).collect::<Vec<_>>();
+        let storage = ChunkStorage::from_blocks(&blocks);
+
+        assert_eq!(storage.bits_per_block(), 8);
+        assert_eq!(storage.to_vec(), blocks);
+
+        let blocks = (0..SIZE).map(|i| (i % 3) as u8 * 2).collect::<Vec<_>>();
+        let storage = ChunkStorage::from_blocks(&blocks);
+
+        assert_eq!(storage.bits_per_block(), 2);
+        assert_eq!(storage.to_vec(), blocks);
+        assert!(storage.memory_usage() < SIZE / 2);
+    }
+
+    #[test]
+    pub fn palette_shrink_test() {
+        let blocks = (0..SIZE).map(|i| (i % 5) as u8).collect::<Vec<_>>();
+        let mut storage = ChunkStorage::from_blocks(&blocks);
+        let usage = storage.memory_usage();
+
+        assert_eq!(storage.bits_per_block(), 4);
+
+        for (i, id) in blocks.iter().enumerate() {
+            if *id > 1 {
+                storage.set(i, 1);
+            }
+        }
+
+        storage.shrink();
+
+        assert_eq!(storage.bits_per_block(), 1);
+        assert_eq!(storage.palette_len(), 2);
+        assert!(storage.memory_usage() < usage);
+
+        for (i, id) in blocks.iter().enumerate() {
+            assert_eq!(storage.get(i), *id.min(&1));
+        }
+
+        for i in 0..SIZE {
+            storage.set(i, 9);
+        }
+
+        assert!(matches!(storage, ChunkStorage::Uniform(9)));
+    }
+
+    #[test]
+    pub fn storage_bytes_round_trip_test() {
+        for blocks in [
+            vec![4; SIZE],
+            (0..SIZE).map(|i| (i % 2) as u8 * 3).collect(),
+            (0..SIZE).map(|i| (i % 37) as u8).collect::<Vec<_>>(),
+        ] {
+            let storage = ChunkStorage::from_blocks(&blocks);
+            let bytes = storage.to_bytes();
+            let read = ChunkStorage::from_bytes(&bytes).unwrap();
+
+            assert_eq!(read.to_vec(), blocks);
+            assert_eq!(read.bits_per_block(), storage.bits_per_block());
+        }
+
+        assert!(ChunkStorage::from_bytes(&[]).is_none());
+        assert!(ChunkStorage::from_bytes(&[9, 0]).is_none());
+        assert!(ChunkStorage::from_bytes(&[1, 2, 3, 0, 1, 2, 3]).is_none());
+    }
+}
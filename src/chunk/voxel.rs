@@ -35,7 +35,7 @@ pub const CUBE_NORMALS: [f32; 24] = [
 impl block_mesh::Voxel for Voxel {
     fn get_visibility(&self) -> block_mesh::VoxelVisibility {
         if self.id == 0 {
-            VoxelVisibility::Translucent
+            VoxelVisibility::Empty
         } else {
             VoxelVisibility::Opaque
         }
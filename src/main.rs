@@ -1,7 +1,7 @@
 // #![windows_subsystem = "windows"]
 use bevy::prelude::PluginGroup;
 use bevy::render::settings::{PowerPreference, WgpuSettings};
-use bevy::ui::{AlignContent, AlignItems};
+use bevy::ui::AlignItems;
 use bevy::window::{CursorGrabMode, WindowMode};
 use bevy::{
     diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
@@ -11,25 +11,35 @@ use bevy::{
         PointLight, PointLightBundle, Query, Res, TextBundle, Transform, Vec3, With,
     },
     text::{Text, TextSection, TextStyle},
-    ui::{PositionType, Style, UiRect, Val},
+    ui::{PositionType, Style},
     window::{PresentMode, WindowDescriptor, WindowPlugin},
     DefaultPlugins,
 };
 use bevy_atmosphere::prelude::{AtmosphereCamera, AtmospherePlugin};
+use bevy_rapier3d::prelude::{NoUserData, RapierPhysicsPlugin};
 
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use camera::CameraController;
 use chunk::container;
-use chunk::container::queue::ChunkUpdateQueue;
+use chunk::generation::GenerationPlugin;
+use chunk::meshing::MeshingMode;
 use chunk::plugin::ChunkPlugin;
 use material::MaterialPlugin;
-use terrain::noise::NoiseData;
+use player::keybinds::KeybindsPlugin;
+use player::physics::{PlayerBody, PlayerPhysicsPlugin};
+use terrain::biome::BiomePlugin;
+use terrain::feature::FeaturePlugin;
+use terrain::horizon::HorizonPlugin;
+use terrain::noise::{NoiseData, WorldSeed};
+use world::{WorldStorage, WorldStoragePlugin};
 
+pub mod asset;
 pub mod camera;
 pub mod chunk;
 pub mod material;
 pub mod player;
 pub mod terrain;
+pub mod world;
 
 fn main() {
     App::new()
@@ -51,9 +61,21 @@ fn main() {
         })
         .insert_resource(NoiseData::new())
         .register_type::<NoiseData>()
-        .add_plugin(FrameTimeDiagnosticsPlugin::default())
+        .insert_resource(WorldSeed::new(0))
+        .insert_resource(MeshingMode::PerMaterial)
+        .register_type::<WorldSeed>()
+        .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_plugin(MaterialPlugin)
+        .add_plugin(BiomePlugin)
+        .add_plugin(FeaturePlugin)
         .add_plugin(ChunkPlugin)
+        .add_plugin(GenerationPlugin)
+        .add_plugin(HorizonPlugin)
+        .add_plugin(KeybindsPlugin)
+        .add_plugin(PlayerPhysicsPlugin)
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+        .insert_resource(WorldStorage::new("world"))
+        .add_plugin(WorldStoragePlugin)
         .add_plugin(WireframePlugin)
         .add_plugin(WorldInspectorPlugin)
         .insert_resource(Msaa { samples: 4 })
@@ -63,6 +85,7 @@ fn main() {
         .add_system(camera::camera_controller)
         .add_system(camera::chunk_loading)
         .add_system(camera::update_mouse)
+        .add_system(camera::edit_blocks)
         .add_system(camera::reset_chunks)
         .add_system(text_update_system)
         .add_system(chunk_update_system)
@@ -93,6 +116,7 @@ pub fn debug_camera(mut commands: Commands, asset_server: Res<AssetServer>) {
             ..Default::default()
         },
         CameraController::default(),
+        PlayerBody::default(),
         AtmosphereCamera::default(),
         // PostProcessingInput,
     ));
@@ -194,36 +218,36 @@ pub struct PosText;
 #[derive(Component)]
 pub struct ChunkUpdatesText;
 
-#[derive(Component)]
-struct CameraIdentifier;
-
 #[cfg(test)]
 mod test {
+    use bevy::prelude::IVec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
     use crate::chunk::{
         container::{Chunks, DomainChunk},
-        X_SIZE, Z_SIZE,
+        X_SIZE, Y_SIZE, Z_SIZE,
     };
 
     #[test]
     pub fn chunk_test() {
         let mut chunks = Chunks::default();
 
-        let chunk1 = chunks.get_chunk_at([5, 5]).world_pos;
-        let chunk2 = chunks.get_chunk_at([0, 0]).world_pos;
-        let chunk3 = chunks.get_chunk_at([3, 2]).world_pos;
-        let chunk4 = chunks.get_chunk_at([32, 31]).world_pos;
-        let chunk5 = chunks.get_chunk_at([-31, -41]).world_pos;
+        let chunk1 = chunks.get_chunk_at([5, 5, 5]).position;
+        let chunk2 = chunks.get_chunk_at([0, 0, 0]).position;
+        let chunk3 = chunks.get_chunk_at([3, 31, 2]).position;
+        let chunk4 = chunks.get_chunk_at([32, 0, 31]).position;
+        let chunk5 = chunks.get_chunk_at([-31, 0, -41]).position;
 
         for i in 0..512 {
             let chunk1 = chunks
-                .get_chunk_at([X_SIZE as i32 * i, Z_SIZE as i32 * i])
-                .world_pos;
+                .get_chunk_at([X_SIZE as i32 * i, 0, Z_SIZE as i32 * i])
+                .position;
             let chunk2 = chunks
-                .get_chunk_at([X_SIZE as i32 * (i + 1), Z_SIZE as i32 * (i + 1)])
-                .world_pos;
+                .get_chunk_at([X_SIZE as i32 * (i + 1), 0, Z_SIZE as i32 * (i + 1)])
+                .position;
 
             assert_eq!(chunk1.x + 1, chunk2.x);
-            assert_eq!(chunk1.y + 1, chunk2.y);
+            assert_eq!(chunk1.z + 1, chunk2.z);
         }
         // for i in
 
@@ -238,18 +262,71 @@ mod test {
         let mut chunks = Chunks::default();
 
         for i in 0..10024 {
-            let chunk1 = chunks.get_domain_at([i, i]).world_pos;
-            let chunk2 = chunks.get_domain_at([i + 1, i + 1]).world_pos;
+            let chunk1 = chunks.get_domain_at([i, i, i]).world_pos;
+            let chunk2 = chunks.get_domain_at([i + 1, i + 1, i + 1]).world_pos;
 
-            assert_eq!(chunk1.x + X_SIZE as i32, chunk2.x as i32);
-            assert_eq!(chunk1.y + Z_SIZE as i32, chunk2.y as i32);
+            assert_eq!(chunk1.x + X_SIZE as i32, chunk2.x);
+            assert_eq!(chunk1.y + Y_SIZE as i32, chunk2.y);
+            assert_eq!(chunk1.z + Z_SIZE as i32, chunk2.z);
         }
 
-        let chunk1 = chunks.get_domain_at([0, 0]).world_pos;
-        let chunk2 = chunks.get_domain_at([0, 0]).world_pos;
-        let chunk3 = chunks.get_domain_at([0, 1]).world_pos;
+        let chunk1 = chunks.get_domain_at([0, 0, 0]).world_pos;
+        let chunk2 = chunks.get_domain_at([0, 0, 0]).world_pos;
+        let chunk3 = chunks.get_domain_at([0, 0, 1]).world_pos;
+        let chunk4 = chunks.get_domain_at([0, 1, 0]).world_pos;
 
         assert_eq!(chunk1, chunk2);
         assert_ne!(chunk2, chunk3);
+        assert_ne!(chunk2, chunk4);
+        assert_ne!(chunk3, chunk4);
+    }
+
+    #[test]
+    pub fn chunk_negative_test() {
+        let mut chunks = Chunks::default();
+
+        for (position, key) in [
+            ([-1, -1, -1], [-1, -1, -1]),
+            ([-32, 0, 0], [-1, 0, 0]),
+            ([-33, -64, 31], [-2, -2, 0]),
+            ([31, 32, -32], [0, 1, -1]),
+            ([0, -33, -33], [0, -2, -2]),
+        ] {
+            let chunk = chunks.get_chunk_at(position);
+
+            assert_eq!(chunk.position, IVec3::from(key));
+            assert_eq!(chunk.world_pos, IVec3::from(Chunks::domain_origin(key)));
+        }
+
+        let far = chunks.get_domain_at([1024, 0, 0]).world_pos;
+        let near = chunks.get_domain_at([0, 0, 0]).world_pos;
+        let negative = chunks.get_domain_at([-1024, 0, 0]).world_pos;
+
+        assert_ne!(far, near);
+        assert_ne!(near, negative);
+        assert_ne!(far, negative);
+    }
+
+    #[test]
+    pub fn chunk_round_trip_test() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let extremes = [i32::MIN, i32::MIN + 1, -1, 0, 1, i32::MAX - 1, i32::MAX];
+
+        let positions = extremes
+            .iter()
+            .flat_map(|x| extremes.iter().map(|y| [*x, *y, 0]))
+            .flat_map(|[x, y, _]| extremes.iter().map(move |z| [x, y, *z]))
+            .chain((0..100_000).map(|_| rng.gen::<[i32; 3]>()));
+
+        for position in positions {
+            let key = Chunks::domain_of(position);
+            let origin = Chunks::domain_origin(key);
+
+            assert_eq!(Chunks::domain_of(origin), key);
+
+            for (axis, size) in [(0, X_SIZE as i32), (1, Y_SIZE as i32), (2, Z_SIZE as i32)] {
+                assert!((0..size).contains(&(position[axis] - origin[axis])));
+            }
+        }
     }
 }
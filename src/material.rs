@@ -1,55 +1,246 @@
-use std::any::TypeId;
+use std::{any::TypeId, fmt::Display, sync::Arc};
 
 use bevy::{
     prelude::{Plugin, ResMut, Resource},
     utils::HashMap,
 };
+use block_mesh::VoxelVisibility;
+use serde::Deserialize;
 
-#[derive(Clone)]
-pub struct Material {}
+use crate::{
+    asset::load_asset,
+    chunk::light::{Light, MAX_LIGHT},
+};
+
+/// Block materials by id. Chunks and region files only store the ids, so changing which
+/// material an id stands for changes every block of it in existing worlds.
+pub const MATERIALS_PATH: &str = "assets/materials.ron";
+
+/// Built-in copy of [`MATERIALS_PATH`], parsed by [`Materials::default`].
+const DEFAULT_MATERIALS: &str = include_str!("../assets/materials.ron");
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Material {
+    pub id: u8,
+    pub name: String,
+    pub color: [f32; 3],
+    #[serde(default = "Material::default_opacity")]
+    pub opacity: f32,
+    #[serde(default = "Material::default_solid")]
+    pub solid: bool,
+    /// Meshed as part of a smooth surface instead of as cubes, see [`MeshingMode`].
+    ///
+    /// [`MeshingMode`]: crate::chunk::meshing::MeshingMode
+    #[serde(default)]
+    pub smooth: bool,
+    /// Strength of the light the block gives off, from `0` for none to `1` for the brightest.
+    #[serde(default)]
+    pub emissive: f32,
+    /// Colour of the light the block gives off, the material's own colour if left out.
+    #[serde(default)]
+    pub emission_color: Option<[f32; 3]>,
+    #[serde(default)]
+    pub textures: FaceTextures,
+    /// Generates veins of this material inside its host block, see [`OrePass`].
+    ///
+    /// [`OrePass`]: crate::terrain::ore::OrePass
+    #[serde(default)]
+    pub ore: Option<OreSettings>,
+}
+
+/// Texture names per block face, `all` is used for every face without a more specific texture.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct FaceTextures {
+    #[serde(default)]
+    pub all: Option<String>,
+    #[serde(default)]
+    pub top: Option<String>,
+    #[serde(default)]
+    pub bottom: Option<String>,
+    #[serde(default)]
+    pub side: Option<String>,
+}
+
+/// Where and how often veins of an ore get generated.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OreSettings {
+    /// Name of the material veins replace, every other block is left alone.
+    pub host: String,
+    /// Lowest and highest world y, inclusive, veins are placed at.
+    pub depth: (i32, i32),
+    /// Steps each vein wanders for, placing at most one block per step.
+    pub vein_size: u32,
+    /// Average number of veins per chunk whose height lies fully within `depth`.
+    pub frequency: f64,
+}
+
+impl Material {
+    fn default_opacity() -> f32 {
+        1.0
+    }
+
+    fn default_solid() -> bool {
+        true
+    }
+
+    /// Shown in place of ids without a definition, so they stand out instead of crashing.
+    pub fn missing() -> Self {
+        Self {
+            id: u8::MAX,
+            name: "missing".into(),
+            color: [1.0, 0.0, 1.0],
+            opacity: 1.0,
+            solid: true,
+            smooth: false,
+            emissive: 0.0,
+            emission_color: None,
+            textures: FaceTextures::default(),
+            ore: None,
+        }
+    }
+
+    /// Fully transparent materials aren't meshed at all, translucent ones like water and glass
+    /// go into a chunk's transparent mesh and don't hide the faces behind them.
+    pub fn visibility(&self) -> VoxelVisibility {
+        if self.opacity <= 0.0 {
+            VoxelVisibility::Empty
+        } else if self.opacity < 1.0 {
+            VoxelVisibility::Translucent
+        } else {
+            VoxelVisibility::Opaque
+        }
+    }
+
+    /// Block light given off by the material, each colour scaled so the strongest one reaches
+    /// the level set by `emissive`.
+    pub fn emission(&self) -> Light {
+        let color = self.emission_color.unwrap_or(self.color);
+        let strongest = color.into_iter().fold(0.0, f32::max);
+
+        if self.emissive <= 0.0 || strongest <= 0.0 {
+            return Light::DARK;
+        }
+
+        let level = self.emissive.clamp(0.0, 1.0) * MAX_LIGHT as f32;
+
+        Light::new(
+            0,
+            color.map(|part| (level * part / strongest).round() as u8),
+        )
+    }
+
+    pub fn rgba(&self) -> [f32; 4] {
+        let [r, g, b] = self.color;
+
+        [r, g, b, self.opacity]
+    }
+}
+
+#[derive(Debug)]
+pub enum MaterialError {
+    Parse(ron::error::SpannedError),
+    /// An ore with a negative or non-finite frequency, or a depth range whose bounds are swapped.
+    InvalidOre(String),
+}
+
+impl Display for MaterialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(error) => write!(f, "{error}"),
+            Self::InvalidOre(name) => write!(
+                f,
+                "ore {name:?} needs a frequency of at least 0 and a depth range from low to high"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MaterialError {}
 
-#[derive(Resource)]
+/// Every material by id, with lookups by name and by type. Meshing jobs clone the registry into
+/// their task, which only copies the `Arc` of the id map.
+#[derive(Resource, Clone)]
 pub struct Materials {
-    id_map: HashMap<u8, Material>,
-    type_map: HashMap<TypeId, Material>,
+    id_map: Arc<HashMap<u8, Material>>,
+    type_map: HashMap<TypeId, u8>,
+    missing: Material,
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        Self::from_ron(DEFAULT_MATERIALS).expect("default materials are valid")
+    }
 }
 
 impl Materials {
-    pub fn get_from_id(&self, id: &u8) -> Option<&Material> {
-        self.id_map.get(id)
+    pub fn from_ron(source: &str) -> Result<Self, MaterialError> {
+        let definitions = ron::from_str::<Vec<Material>>(source).map_err(MaterialError::Parse)?;
+
+        // vein placement samples with these, better to refuse the file than crash a worker
+        for material in &definitions {
+            let Some(ore) = &material.ore else {
+                continue;
+            };
+
+            if !(ore.frequency.is_finite() && ore.frequency >= 0.0) || ore.depth.0 > ore.depth.1 {
+                return Err(MaterialError::InvalidOre(material.name.clone()));
+            }
+        }
+
+        let mut materials = Self {
+            id_map: Arc::new(
+                definitions
+                    .into_iter()
+                    .map(|material| (material.id, material))
+                    .collect(),
+            ),
+            type_map: HashMap::new(),
+            missing: Material::missing(),
+        };
+
+        for (ty, name) in [
+            (TypeId::of::<Void>(), "void"),
+            (TypeId::of::<Grass>(), "grass"),
+            (TypeId::of::<Stone>(), "stone"),
+            (TypeId::of::<Water>(), "water"),
+            (TypeId::of::<Sand>(), "sand"),
+        ] {
+            if let Some(id) = materials.id_of(name) {
+                materials.type_map.insert(ty, id);
+            }
+        }
+
+        Ok(materials)
+    }
+
+    /// Returns the material with the given id, or the [`Material::missing`] material.
+    pub fn get_from_id(&self, id: u8) -> &Material {
+        self.id_map.get(&id).unwrap_or(&self.missing)
     }
 
     pub fn get<T: 'static>(&self) -> Option<&Material> {
-        self.type_map.get(&TypeId::of::<T>())
+        self.type_map
+            .get(&TypeId::of::<T>())
+            .map(|id| self.get_from_id(*id))
     }
-}
 
-pub const MAT_COLORS: [[f32; 4]; 5] = [
-    [0.0, 137.0 / 255.0, 32.0 / 255.0, 0.0],            // void
-    [0.0, 137.0 / 255.0, 32.0 / 255.0, 1.0],            // grass
-    [145.0 / 255.0, 142.0 / 255.0, 133.0 / 255.0, 1.0], // stone,
-    [0.0, 0.0, 137.0 / 255.0, 0.63],                    // water
-    [255.0 / 255.0, 229.0 / 255.0, 153.0 / 255.0, 1.0], // sand
-];
+    pub fn iter(&self) -> impl Iterator<Item = &Material> {
+        self.id_map.values()
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<u8> {
+        self.id_map
+            .values()
+            .find(|material| material.name == name)
+            .map(|material| material.id)
+    }
+}
 
 impl MaterialPlugin {
     pub fn init_materials(mut materials: ResMut<Materials>) {
-        let mut id_map = HashMap::<u8, Material>::new();
-        let mut type_map = HashMap::<TypeId, Material>::new();
-
-        for (id, ty, material) in [
-            (0, TypeId::of::<Void>(), Material {}),
-            (1, TypeId::of::<Grass>(), Material {}),
-            (2, TypeId::of::<Stone>(), Material {}),
-            (3, TypeId::of::<Water>(), Material {}),
-            (4, TypeId::of::<Sand>(), Material {}),
-        ] {
-            id_map.insert(id, material.clone());
-            type_map.insert(ty, material.clone());
+        if let Some(loaded) = load_asset(MATERIALS_PATH, "materials", Materials::from_ron) {
+            *materials = loaded;
         }
-
-        materials.id_map = id_map;
-        materials.type_map = type_map;
     }
 }
 
@@ -58,10 +249,7 @@ pub struct MaterialPlugin;
 impl Plugin for MaterialPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.add_startup_system(Self::init_materials)
-            .insert_resource(Materials {
-                id_map: HashMap::new(),
-                type_map: HashMap::new(),
-            });
+            .insert_resource(Materials::default());
     }
 }
 
@@ -71,3 +259,79 @@ pub struct Void;
 pub struct Stone;
 pub struct Water;
 pub struct Sand;
+
+#[cfg(test)]
+mod test {
+    use block_mesh::VoxelVisibility;
+
+    use super::{MaterialError, Materials, Stone, Water};
+    use crate::chunk::light::Light;
+
+    #[test]
+    pub fn default_materials_test() {
+        let materials = Materials::default();
+
+        assert_eq!(materials.get_from_id(0).name, "void");
+        assert_eq!(materials.get::<Stone>().unwrap().id, 2);
+        assert!(materials.get::<Stone>().unwrap().smooth);
+        assert_eq!(materials.id_of("sand"), Some(4));
+
+        let water = materials.get::<Water>().unwrap();
+
+        assert!(!water.solid);
+        assert!(water.opacity < 1.0);
+        assert_eq!(water.visibility(), VoxelVisibility::Translucent);
+        assert_eq!(
+            materials.get_from_id(0).visibility(),
+            VoxelVisibility::Empty
+        );
+        assert_eq!(
+            materials.get_from_id(9).visibility(),
+            VoxelVisibility::Translucent
+        );
+        assert_eq!(water.emissive, 0.0);
+        assert_eq!(water.emission(), Light::DARK);
+        assert_eq!(
+            materials
+                .id_of("lava")
+                .map(|id| materials.get_from_id(id).emission()),
+            Some(Light::new(0, [15, 6, 0]))
+        );
+        assert_eq!(
+            materials
+                .id_of("lamp")
+                .map(|id| materials.get_from_id(id).emission()),
+            Some(Light::new(0, [14, 11, 8]))
+        );
+
+        let grass = materials.get_from_id(1);
+
+        assert_eq!(grass.textures.top.as_deref(), Some("grass_top"));
+        assert_eq!(grass.textures.all, None);
+    }
+
+    #[test]
+    pub fn missing_material_test() {
+        let materials =
+            Materials::from_ron("[(id: 7, name: \"lamp\", color: (1.0, 1.0, 0.5), emissive: 0.8)]")
+                .unwrap();
+
+        assert_eq!(materials.get_from_id(7).emissive, 0.8);
+        assert!(materials.get_from_id(7).solid);
+        assert_eq!(materials.get_from_id(0).name, "missing");
+        assert_eq!(materials.get_from_id(200).name, "missing");
+        assert!(materials.get::<Stone>().is_none());
+
+        assert!(matches!(
+            Materials::from_ron("[(id: 1)]"),
+            Err(MaterialError::Parse(_))
+        ));
+
+        let negative = Materials::from_ron(
+            r#"[(id: 5, name: "coal", color: (0.1, 0.1, 0.1),
+                ore: Some((host: "stone", depth: (-96, 48), vein_size: 12, frequency: -1.0)))]"#,
+        );
+
+        assert!(matches!(negative, Err(MaterialError::InvalidOre(name)) if name == "coal"));
+    }
+}
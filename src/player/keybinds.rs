@@ -1,26 +1,188 @@
-use bevy::prelude::{KeyCode, Resource};
-
-#[derive(Resource)]
-pub struct Keybinds {
-    pub key_forward: KeyCode,
-    pub key_back: KeyCode,
-    pub key_left: KeyCode,
-    pub key_right: KeyCode,
-    pub key_up: KeyCode,
-    pub key_down: KeyCode,
-    pub key_run: KeyCode,
-}
-
-impl Default for Keybinds {
-    fn default() -> Self {
-        Self {
-            key_forward: KeyCode::W,
-            key_back: KeyCode::S,
-            key_left: KeyCode::A,
-            key_right: KeyCode::D,
-            key_up: KeyCode::Space,
-            key_down: KeyCode::LShift,
-            key_run: KeyCode::LControl,
+use std::{fmt::Display, fs, path::Path};
+
+use bevy::{
+    log::{error, info},
+    prelude::{GamepadButtonType, KeyCode, MouseButton, Plugin, Res, ResMut},
+};
+use leafwing_input_manager::{
+    axislike::{DualAxis, VirtualDPad},
+    prelude::{ActionState, InputManagerPlugin, InputMap},
+    Actionlike,
+};
+use serde::{Deserialize, Serialize};
+
+/// Path of the keybinds, relative to the working directory. It is written with the defaults
+/// when missing, and again whenever the bindings change.
+pub const KEYBINDS_PATH: &str = "config/keybinds.ron";
+
+/// Everything the player can do, bound to keys, mouse buttons and gamepad inputs by an
+/// [`InputMap`] loaded from [`KEYBINDS_PATH`].
+#[derive(
+    Actionlike, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Action {
+    /// Moves along the ground, `y` is forward and `x` is right.
+    Move,
+    /// Turns the camera with a gamepad stick, the mouse turns it directly.
+    Look,
+    /// Flies up, or jumps while walking.
+    Up,
+    /// Flies down.
+    Down,
+    Run,
+    Break,
+    Place,
+    ToggleFly,
+    ToggleWireframe,
+    ResetChunks,
+}
+
+/// The bindings used when [`KEYBINDS_PATH`] doesn't exist yet.
+pub fn default_keybinds() -> InputMap<Action> {
+    let mut keybinds = InputMap::default();
+
+    keybinds
+        .insert(VirtualDPad::wasd(), Action::Move)
+        .insert(DualAxis::left_stick(), Action::Move)
+        .insert(DualAxis::right_stick(), Action::Look)
+        .insert(KeyCode::E, Action::Up)
+        .insert(KeyCode::Space, Action::Up)
+        .insert(GamepadButtonType::South, Action::Up)
+        .insert(KeyCode::Q, Action::Down)
+        .insert(GamepadButtonType::East, Action::Down)
+        .insert(KeyCode::LShift, Action::Run)
+        .insert(GamepadButtonType::LeftThumb, Action::Run)
+        .insert(MouseButton::Left, Action::Break)
+        .insert(GamepadButtonType::RightTrigger2, Action::Break)
+        .insert(MouseButton::Right, Action::Place)
+        .insert(GamepadButtonType::LeftTrigger2, Action::Place)
+        .insert(KeyCode::F, Action::ToggleFly)
+        .insert(GamepadButtonType::North, Action::ToggleFly)
+        .insert(KeyCode::Grave, Action::ToggleWireframe)
+        .insert(GamepadButtonType::Select, Action::ToggleWireframe)
+        .insert(KeyCode::R, Action::ResetChunks);
+
+    keybinds
+}
+
+#[derive(Debug)]
+pub enum KeybindsError {
+    Io(std::io::Error),
+    Parse(ron::error::SpannedError),
+    Serialize(ron::Error),
+}
+
+impl Display for KeybindsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{error}"),
+            Self::Parse(error) => write!(f, "{error}"),
+            Self::Serialize(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for KeybindsError {}
+
+pub fn load_keybinds(path: impl AsRef<Path>) -> Result<InputMap<Action>, KeybindsError> {
+    let source = fs::read_to_string(path).map_err(KeybindsError::Io)?;
+
+    ron::from_str(&source).map_err(KeybindsError::Parse)
+}
+
+pub fn save_keybinds(
+    keybinds: &InputMap<Action>,
+    path: impl AsRef<Path>,
+) -> Result<(), KeybindsError> {
+    let path = path.as_ref();
+    let source = ron::ser::to_string_pretty(keybinds, ron::ser::PrettyConfig::default())
+        .map_err(KeybindsError::Serialize)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(KeybindsError::Io)?;
+    }
+
+    fs::write(path, source).map_err(KeybindsError::Io)
+}
+
+pub struct KeybindsPlugin;
+
+impl KeybindsPlugin {
+    pub fn init_keybinds(mut keybinds: ResMut<InputMap<Action>>) {
+        match load_keybinds(KEYBINDS_PATH) {
+            Ok(loaded) => *keybinds = loaded,
+            Err(KeybindsError::Io(error)) if error.kind() == std::io::ErrorKind::NotFound => {
+                info!("no keybinds at {KEYBINDS_PATH}, writing the defaults there");
+
+                if let Err(error) = save_keybinds(&keybinds, KEYBINDS_PATH) {
+                    error!("failed to save {KEYBINDS_PATH}: {error}");
+                }
+            }
+            Err(error) => {
+                error!("failed to load {KEYBINDS_PATH}, using the default keybinds: {error}")
+            }
+        }
+    }
+
+    /// Writes rebound keys back to [`KEYBINDS_PATH`].
+    pub fn save_changed_keybinds(keybinds: Res<InputMap<Action>>) {
+        if !keybinds.is_changed() || keybinds.is_added() {
+            return;
+        }
+
+        if let Err(error) = save_keybinds(&keybinds, KEYBINDS_PATH) {
+            error!("failed to save {KEYBINDS_PATH}: {error}");
         }
     }
 }
+
+impl Plugin for KeybindsPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.add_plugin(InputManagerPlugin::<Action>::default())
+            .init_resource::<ActionState<Action>>()
+            .insert_resource(default_keybinds())
+            .add_startup_system(Self::init_keybinds)
+            .add_system(Self::save_changed_keybinds);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use bevy::prelude::{GamepadButtonType, KeyCode};
+    use leafwing_input_manager::user_input::UserInput;
+
+    use super::{default_keybinds, load_keybinds, save_keybinds, Action, KeybindsError};
+
+    #[test]
+    pub fn keybinds_round_trip_test() {
+        let keybinds = default_keybinds();
+
+        assert!(keybinds
+            .get(Action::Up)
+            .contains(&UserInput::from(KeyCode::E)));
+        assert!(keybinds
+            .get(Action::Up)
+            .contains(&UserInput::from(GamepadButtonType::South)));
+
+        let path = env::temp_dir()
+            .join(format!("voxel-keybinds-{}", std::process::id()))
+            .join("keybinds.ron");
+
+        assert!(matches!(load_keybinds(&path), Err(KeybindsError::Io(_))));
+
+        let mut rebound = keybinds.clone();
+
+        rebound.clear_action(Action::Up);
+        rebound.insert(KeyCode::J, Action::Up);
+        save_keybinds(&rebound, &path).unwrap();
+
+        let loaded = load_keybinds(&path).unwrap();
+
+        assert_eq!(loaded, rebound);
+        assert_ne!(loaded, keybinds);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -2,6 +2,7 @@ use bevy::prelude::Vec3;
 
 pub mod camera;
 pub mod keybinds;
+pub mod physics;
 
 pub struct Player {
     pub walk_speed: f32,
@@ -0,0 +1,458 @@
+use bevy::{
+    prelude::{
+        BVec3, Camera, Component, IVec3, Plugin, Query, Res, Resource, Transform, Vec2, Vec3, With,
+    },
+    time::Time,
+};
+use leafwing_input_manager::prelude::ActionState;
+
+use crate::{
+    camera::CameraController,
+    chunk::container::{loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk},
+    material::Materials,
+    player::keybinds::Action,
+};
+
+/// Longest time step the player is simulated with, so a frame hitch doesn't turn into one
+/// huge jump through the world.
+const MAX_TIME_STEP: f32 = 0.05;
+
+/// Axis aligned box in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// A box of `size` whose bottom face is centred on `feet`.
+    pub fn from_feet(feet: Vec3, size: Vec3) -> Self {
+        let half = Vec3::new(size.x, 0.0, size.z) / 2.0;
+
+        Self::new(feet - half, feet + half + Vec3::Y * size.y)
+    }
+
+    /// Centre of the bottom face.
+    pub fn feet(&self) -> Vec3 {
+        Vec3::new(
+            (self.min.x + self.max.x) / 2.0,
+            self.min.y,
+            (self.min.z + self.max.z) / 2.0,
+        )
+    }
+
+    pub fn translate(self, offset: Vec3) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    /// Smallest box containing both boxes.
+    pub fn union(self, other: Aabb) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    /// Cuts `motion` along `axis` short where this box would start overlapping `other`.
+    fn clip(&self, other: &Aabb, axis: usize, motion: f32) -> f32 {
+        let overlaps = (0..3)
+            .filter(|other_axis| *other_axis != axis)
+            .all(|other_axis| {
+                self.min[other_axis] < other.max[other_axis]
+                    && self.max[other_axis] > other.min[other_axis]
+            });
+
+        if !overlaps {
+            motion
+        } else if motion > 0.0 && self.max[axis] <= other.min[axis] {
+            motion.min(other.min[axis] - self.max[axis])
+        } else if motion < 0.0 && self.min[axis] >= other.max[axis] {
+            motion.max(other.max[axis] - self.min[axis])
+        } else {
+            motion
+        }
+    }
+}
+
+/// Result of [`Chunks::sweep_aabb`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sweep {
+    pub aabb: Aabb,
+    /// Axes along which the motion was cut short by a block.
+    pub blocked: BVec3,
+}
+
+impl Chunks {
+    /// Moves `aabb` by `motion` one axis at a time, vertically first, stopping it flush against
+    /// the first solid block along each axis, so it slides along whatever it runs into.
+    ///
+    /// Every block the motion passes is checked, no matter how far it goes.
+    pub fn sweep_aabb(&self, materials: &Materials, aabb: Aabb, motion: Vec3) -> Sweep {
+        let reach = aabb.union(aabb.translate(motion));
+        let min = reach.min.floor().as_ivec3();
+        let max = reach.max.ceil().as_ivec3();
+        let mut blocks = Vec::new();
+
+        for z in min.z..max.z {
+            for y in min.y..max.y {
+                for x in min.x..max.x {
+                    let position = IVec3::new(x, y, z);
+
+                    if materials.get_from_id(self.get_block_world(position)).solid {
+                        let corner = position.as_vec3();
+
+                        blocks.push(Aabb::new(corner, corner + Vec3::ONE));
+                    }
+                }
+            }
+        }
+
+        let mut aabb = aabb;
+        let mut blocked = BVec3::FALSE;
+
+        for axis in [1, 0, 2] {
+            let wanted = motion[axis];
+            let moved = blocks
+                .iter()
+                .fold(wanted, |moved, block| aabb.clip(block, axis, moved));
+            let mut offset = Vec3::ZERO;
+
+            offset[axis] = moved;
+            aabb = aabb.translate(offset);
+
+            if moved != wanted {
+                match axis {
+                    0 => blocked.x = true,
+                    1 => blocked.y = true,
+                    _ => blocked.z = true,
+                }
+            }
+        }
+
+        Sweep { aabb, blocked }
+    }
+}
+
+/// Tuning of the walking player.
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct PlayerPhysics {
+    /// Downwards acceleration in blocks per second squared.
+    pub gravity: f32,
+    /// Upwards speed a jump starts with.
+    pub jump_speed: f32,
+    /// Highest ledge that is climbed by walking into it.
+    pub step_height: f32,
+    pub walk_speed: f32,
+    pub run_speed: f32,
+    pub max_fall_speed: f32,
+}
+
+impl Default for PlayerPhysics {
+    fn default() -> Self {
+        Self {
+            gravity: 28.0,
+            jump_speed: 8.5,
+            step_height: 1.0,
+            walk_speed: 4.5,
+            run_speed: 7.5,
+            max_fall_speed: 50.0,
+        }
+    }
+}
+
+/// Collider of the player while walking, see [`CameraController::flying`].
+#[derive(Component, Debug, Clone)]
+pub struct PlayerBody {
+    /// Width, height and depth of the collider.
+    pub size: Vec3,
+    /// Height of the camera above the feet.
+    pub eye_height: f32,
+    pub velocity: Vec3,
+    pub on_ground: bool,
+}
+
+impl Default for PlayerBody {
+    fn default() -> Self {
+        Self {
+            size: Vec3::new(0.6, 1.8, 0.6),
+            eye_height: 1.62,
+            velocity: Vec3::ZERO,
+            on_ground: false,
+        }
+    }
+}
+
+impl PlayerBody {
+    /// Advances the body standing at `feet` by `dt` seconds and returns where its feet end up.
+    ///
+    /// `walk` is the horizontal velocity the player wants to move with, and `jump` starts a
+    /// jump when the body stands on the ground.
+    #[allow(clippy::too_many_arguments)]
+    pub fn step(
+        &mut self,
+        chunks: &Chunks,
+        materials: &Materials,
+        physics: &PlayerPhysics,
+        feet: Vec3,
+        walk: Vec3,
+        jump: bool,
+        dt: f32,
+    ) -> Vec3 {
+        self.velocity.x = walk.x;
+        self.velocity.z = walk.z;
+
+        if jump && self.on_ground {
+            self.velocity.y = physics.jump_speed;
+        }
+
+        self.velocity.y = (self.velocity.y - physics.gravity * dt).max(-physics.max_fall_speed);
+
+        let aabb = Aabb::from_feet(feet, self.size);
+        let motion = self.velocity * dt;
+        let mut sweep = chunks.sweep_aabb(materials, aabb, motion);
+
+        // walked into a ledge, try climbing on top of it
+        if self.on_ground && (sweep.blocked.x || sweep.blocked.z) && physics.step_height > 0.0 {
+            let horizontal = Vec3::new(motion.x, 0.0, motion.z);
+            let up = chunks.sweep_aabb(materials, aabb, Vec3::Y * physics.step_height);
+            let across = chunks.sweep_aabb(materials, up.aabb, horizontal);
+            let down = chunks.sweep_aabb(
+                materials,
+                across.aabb,
+                Vec3::NEG_Y * (up.aabb.min.y - aabb.min.y),
+            );
+
+            let travelled = |sweep: &Sweep| (sweep.aabb.min - aabb.min) * Vec3::new(1.0, 0.0, 1.0);
+
+            if travelled(&down).length_squared() > travelled(&sweep).length_squared() {
+                sweep = Sweep {
+                    aabb: down.aabb,
+                    blocked: BVec3::new(across.blocked.x, down.blocked.y, across.blocked.z),
+                };
+                self.velocity.y = self.velocity.y.min(0.0);
+            }
+        }
+
+        self.on_ground = sweep.blocked.y && self.velocity.y <= 0.0;
+
+        for (blocked, velocity) in [
+            (sweep.blocked.x, &mut self.velocity.x),
+            (sweep.blocked.y, &mut self.velocity.y),
+            (sweep.blocked.z, &mut self.velocity.z),
+        ] {
+            if blocked {
+                *velocity = 0.0;
+            }
+        }
+
+        sweep.aabb.feet()
+    }
+}
+
+pub struct PlayerPhysicsPlugin;
+
+impl PlayerPhysicsPlugin {
+    /// Walks the camera through the world while it isn't flying.
+    #[allow(clippy::too_many_arguments)]
+    pub fn walk(
+        time: Res<Time>,
+        actions: Res<ActionState<Action>>,
+        chunks: Res<Chunks>,
+        loaded_chunks: Res<LoadedChunks>,
+        materials: Res<Materials>,
+        physics: Res<PlayerPhysics>,
+        mut query: Query<(&mut Transform, &CameraController, &mut PlayerBody), With<Camera>>,
+    ) {
+        let Ok((mut transform, options, mut body)) = query.get_single_mut() else {
+            return;
+        };
+
+        if options.flying {
+            body.velocity = Vec3::ZERO;
+            body.on_ground = false;
+            return;
+        }
+
+        if !options.enabled {
+            return;
+        }
+
+        let feet = transform.translation - Vec3::Y * body.eye_height;
+        let key = ChunkKey::from(Chunks::domain_of(feet.floor().as_ivec3().to_array()));
+
+        // hold still until the ground around the player exists
+        if !loaded_chunks.is_chunk_id_loaded(&key) {
+            return;
+        }
+
+        let flat = |direction: Vec3| (direction * Vec3::new(1.0, 0.0, 1.0)).normalize_or_zero();
+        let (forward, right) = (flat(transform.forward()), flat(transform.right()));
+        let movement = actions
+            .axis_pair(Action::Move)
+            .map_or(Vec2::ZERO, |axis| axis.xy());
+        let walk = (forward * movement.y + right * movement.x).clamp_length_max(1.0);
+
+        let speed = if actions.pressed(Action::Run) {
+            physics.run_speed
+        } else {
+            physics.walk_speed
+        };
+
+        let feet = body.step(
+            &chunks,
+            &materials,
+            &physics,
+            feet,
+            walk * speed,
+            actions.pressed(Action::Up),
+            time.delta_seconds().min(MAX_TIME_STEP),
+        );
+
+        transform.translation = feet + Vec3::Y * body.eye_height;
+    }
+}
+
+impl Plugin for PlayerPhysicsPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<PlayerPhysics>().add_system(Self::walk);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::{IVec3, Vec3};
+
+    use super::{Aabb, PlayerBody, PlayerPhysics};
+    use crate::{
+        chunk::{
+            container::{Chunks, DomainChunk},
+            storage::ChunkStorage,
+        },
+        material::Materials,
+    };
+
+    /// Stone below y = 0 and air above it.
+    fn flat_world() -> Chunks {
+        let mut chunks = Chunks::default();
+
+        for z in -1..=1 {
+            for x in -1..=1 {
+                chunks
+                    .get_domain_at_mut([x, -1, z])
+                    .override_blocks(ChunkStorage::Uniform(2));
+            }
+        }
+
+        chunks
+    }
+
+    #[test]
+    pub fn aabb_sweep_test() {
+        let materials = Materials::default();
+        let mut chunks = flat_world();
+        let size = Vec3::new(0.6, 1.8, 0.6);
+
+        // falling onto the ground stops flush on top of it
+        let sweep = chunks.sweep_aabb(
+            &materials,
+            Aabb::from_feet(Vec3::new(0.5, 3.0, 0.5), size),
+            Vec3::new(0.0, -5.0, 0.0),
+        );
+
+        assert_eq!(sweep.aabb.feet(), Vec3::new(0.5, 0.0, 0.5));
+        assert!(sweep.blocked.y && !sweep.blocked.x && !sweep.blocked.z);
+
+        // however fast, nothing tunnels through a single block of floor
+        chunks.set_block_world(IVec3::new(5, 20, 5), 2);
+
+        let sweep = chunks.sweep_aabb(
+            &materials,
+            Aabb::from_feet(Vec3::new(5.5, 22.0, 5.5), size),
+            Vec3::new(0.0, -1000.0, 0.0),
+        );
+
+        assert_eq!(sweep.aabb.feet().y, 21.0);
+
+        // running diagonally into a wall slides along it
+        for y in 0..3 {
+            chunks.set_block_world(IVec3::new(3, y, 0), 2);
+        }
+
+        let sweep = chunks.sweep_aabb(
+            &materials,
+            Aabb::from_feet(Vec3::new(1.5, 0.0, 0.5), size),
+            Vec3::new(2.0, 0.0, 0.25),
+        );
+
+        assert_eq!(sweep.aabb.max.x, 3.0);
+        assert_eq!(sweep.aabb.feet().z, 0.75);
+        assert!(sweep.blocked.x && !sweep.blocked.z);
+
+        // water doesn't get in the way
+        chunks.set_block_world(IVec3::new(-3, 0, 0), 3);
+
+        let sweep = chunks.sweep_aabb(
+            &materials,
+            Aabb::from_feet(Vec3::new(-1.5, 0.0, 0.5), size),
+            Vec3::new(-3.0, 0.0, 0.0),
+        );
+
+        assert!(!sweep.blocked.x);
+    }
+
+    #[test]
+    pub fn player_step_test() {
+        let materials = Materials::default();
+        let physics = PlayerPhysics::default();
+        let mut chunks = flat_world();
+        let mut body = PlayerBody::default();
+        let mut feet = Vec3::new(0.5, 4.0, 0.5);
+        let step = |chunks: &Chunks, body: &mut PlayerBody, feet, walk, jump| {
+            body.step(chunks, &materials, &physics, feet, walk, jump, 0.02)
+        };
+
+        for _ in 0..100 {
+            feet = step(&chunks, &mut body, feet, Vec3::ZERO, false);
+        }
+
+        assert_eq!(feet.y, 0.0);
+        assert!(body.on_ground);
+
+        // jumping leaves the ground and comes back down
+        let mut peak = 0.0f32;
+
+        for i in 0..100 {
+            feet = step(&chunks, &mut body, feet, Vec3::ZERO, i == 0);
+            peak = peak.max(feet.y);
+        }
+
+        assert!(peak > 1.0 && peak < 1.5, "jumped {peak} blocks");
+        assert_eq!(feet.y, 0.0);
+        assert!(body.on_ground);
+
+        // a single block ledge is climbed by walking into it, a wall of two isn't
+        for x in 2..6 {
+            chunks.set_block_world(IVec3::new(x, 0, 0), 2);
+        }
+
+        for y in 1..3 {
+            chunks.set_block_world(IVec3::new(5, y, 0), 2);
+        }
+
+        for _ in 0..100 {
+            feet = step(
+                &chunks,
+                &mut body,
+                feet,
+                Vec3::X * physics.walk_speed,
+                false,
+            );
+        }
+
+        assert_eq!(feet.y, 1.0);
+        assert!((feet.x - (5.0 - body.size.x / 2.0)).abs() < 1e-4);
+        assert!(body.on_ground);
+        assert_eq!(body.velocity.x, 0.0);
+    }
+}
@@ -0,0 +1,378 @@
+use std::{fmt::Display, sync::Arc};
+
+use bevy::prelude::{IntoSystemDescriptor, Plugin, Res, ResMut, Resource};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
+use serde::Deserialize;
+
+use crate::{
+    asset::load_asset,
+    material::{MaterialPlugin, Materials},
+};
+
+use super::noise::{create_fbm, sample_height, NoiseData};
+
+/// Biomes placed by temperature and humidity. Their blocks are given by material name, so the
+/// file is only read after [`MaterialPlugin::init_materials`].
+pub const BIOMES_PATH: &str = "assets/biomes.ron";
+
+/// Built-in copy of [`BIOMES_PATH`], resolved against the built-in materials by
+/// [`Biomes::default`].
+const DEFAULT_BIOMES: &str = include_str!("../../assets/biomes.ron");
+
+/// Frequency of the temperature and humidity noise, so biomes span hundreds of blocks.
+const CLIMATE_SCALE: f64 = 1.0 / 512.0;
+
+/// Distance in climate space over which the heights of neighbouring biomes blend.
+const BLEND_WIDTH: f64 = 0.08;
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct HeightCurve {
+    pub base: f64,
+    pub amplitude: f64,
+    #[serde(default = "HeightCurve::default_exponent")]
+    pub exponent: f64,
+}
+
+impl HeightCurve {
+    fn default_exponent() -> f64 {
+        1.0
+    }
+
+    /// Height in blocks for the raw terrain noise `value`, which lies between -1 and 1.
+    pub fn apply(&self, value: f64, height_scale: f64) -> f64 {
+        let shaped = ((value + 1.0) / 2.0).clamp(0.0, 1.0).powf(self.exponent);
+
+        self.base + (shaped * 2.0 - 1.0) * self.amplitude * height_scale
+    }
+}
+
+/// A block placed on top of the surface of a column, with the given chance per column.
+#[derive(Clone, Copy, Debug)]
+pub struct Decoration {
+    pub block: u8,
+    pub chance: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Biome {
+    pub name: String,
+    pub temperature: f64,
+    pub humidity: f64,
+    pub surface: u8,
+    pub filler: u8,
+    /// Blocks of filler below the surface block, before the ground turns into stone.
+    pub filler_depth: i32,
+    pub height: HeightCurve,
+    pub decorations: Vec<Decoration>,
+}
+
+impl Biome {
+    /// Picks the decoration for a column from its `roll`, if it gets one.
+    pub fn decoration(&self, roll: f64) -> Option<u8> {
+        let mut chance = 0.0;
+
+        self.decorations.iter().find_map(|decoration| {
+            chance += decoration.chance;
+            (roll < chance).then_some(decoration.block)
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct DecorationDefinition {
+    block: String,
+    chance: f64,
+}
+
+#[derive(Deserialize)]
+struct BiomeDefinition {
+    name: String,
+    temperature: f64,
+    humidity: f64,
+    surface: String,
+    filler: String,
+    #[serde(default = "BiomeDefinition::default_filler_depth")]
+    filler_depth: i32,
+    height: HeightCurve,
+    #[serde(default)]
+    decorations: Vec<DecorationDefinition>,
+}
+
+impl BiomeDefinition {
+    fn default_filler_depth() -> i32 {
+        3
+    }
+}
+
+#[derive(Debug)]
+pub enum BiomeError {
+    Parse(ron::error::SpannedError),
+    UnknownBlock(String),
+    Empty,
+}
+
+impl Display for BiomeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(error) => write!(f, "{error}"),
+            Self::UnknownBlock(name) => write!(f, "no material is named {name:?}"),
+            Self::Empty => write!(f, "at least one biome is required"),
+        }
+    }
+}
+
+impl std::error::Error for BiomeError {}
+
+/// Every biome, shared behind an `Arc` with the generation tasks sampling them.
+#[derive(Resource, Clone)]
+pub struct Biomes {
+    biomes: Arc<Vec<Biome>>,
+}
+
+impl Default for Biomes {
+    fn default() -> Self {
+        Self::from_ron(DEFAULT_BIOMES, &Materials::default()).expect("default biomes are valid")
+    }
+}
+
+impl Biomes {
+    /// Parses biome definitions, resolving their block names through `materials`.
+    pub fn from_ron(source: &str, materials: &Materials) -> Result<Self, BiomeError> {
+        let definitions =
+            ron::from_str::<Vec<BiomeDefinition>>(source).map_err(BiomeError::Parse)?;
+
+        if definitions.is_empty() {
+            return Err(BiomeError::Empty);
+        }
+
+        let block = |name: &str| {
+            materials
+                .id_of(name)
+                .ok_or_else(|| BiomeError::UnknownBlock(name.into()))
+        };
+
+        let biomes = definitions
+            .into_iter()
+            .map(|definition| {
+                Ok(Biome {
+                    surface: block(&definition.surface)?,
+                    filler: block(&definition.filler)?,
+                    decorations: definition
+                        .decorations
+                        .iter()
+                        .map(|decoration| {
+                            Ok(Decoration {
+                                block: block(&decoration.block)?,
+                                chance: decoration.chance,
+                            })
+                        })
+                        .collect::<Result<_, BiomeError>>()?,
+                    name: definition.name,
+                    temperature: definition.temperature,
+                    humidity: definition.humidity,
+                    filler_depth: definition.filler_depth,
+                    height: definition.height,
+                })
+            })
+            .collect::<Result<Vec<_>, BiomeError>>()?;
+
+        Ok(Self {
+            biomes: Arc::new(biomes),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Biome> {
+        self.biomes.iter()
+    }
+
+    pub fn sampler<'a>(&'a self, noise_data: &'a NoiseData, seed: u32) -> BiomeSampler<'a> {
+        let climate = |seed| Fbm::<Perlin>::new(seed).set_octaves(3);
+
+        BiomeSampler {
+            biomes: &self.biomes,
+            noise_data,
+            height: create_fbm(noise_data, seed),
+            temperature: climate(seed.wrapping_add(1)),
+            humidity: climate(seed.wrapping_add(2)),
+            seed,
+        }
+    }
+}
+
+/// What a [`TerrainGenerator`](super::TerrainGenerator) knows about the column it fills.
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeContext<'a> {
+    /// The biome with the strongest influence on the column.
+    pub biome: &'a Biome,
+    /// Surface height blended across nearby biomes, blocks are filled up to and including it.
+    pub height: f64,
+    pub temperature: f64,
+    pub humidity: f64,
+    /// Highest y filled with water where it lies above the surface.
+    pub sea_level: i32,
+    /// Random value between 0 and 1, the same for every block of the column.
+    pub roll: f64,
+}
+
+/// Samples the climate and blended height of columns for one seed.
+pub struct BiomeSampler<'a> {
+    biomes: &'a [Biome],
+    noise_data: &'a NoiseData,
+    height: Fbm<Perlin>,
+    temperature: Fbm<Perlin>,
+    humidity: Fbm<Perlin>,
+    seed: u32,
+}
+
+impl<'a> BiomeSampler<'a> {
+    pub fn sample(&self, x: i32, z: i32) -> BiomeContext<'a> {
+        let climate = |fbm: &Fbm<Perlin>| {
+            let value = fbm.get([x as f64 * CLIMATE_SCALE, z as f64 * CLIMATE_SCALE]);
+
+            ((value + 1.0) / 2.0).clamp(0.0, 1.0)
+        };
+
+        let temperature = climate(&self.temperature);
+        let humidity = climate(&self.humidity);
+        let value = sample_height(&self.height, self.noise_data, x, z);
+
+        let mut biome = &self.biomes[0];
+        let mut strongest = f64::MIN;
+        let mut total = 0.0;
+        let mut height = 0.0;
+
+        // every biome pulls on the height, falling off with its distance in climate space, so
+        // there are no cliffs where the dominant biome changes
+        for candidate in self.biomes {
+            let distance = (candidate.temperature - temperature).powi(2)
+                + (candidate.humidity - humidity).powi(2);
+            let weight = (-distance / BLEND_WIDTH.powi(2)).exp();
+
+            if weight > strongest {
+                strongest = weight;
+                biome = candidate;
+            }
+
+            total += weight;
+            height += weight
+                * candidate
+                    .height
+                    .apply(value, self.noise_data.height_scale());
+        }
+
+        BiomeContext {
+            biome,
+            height: if total > 0.0 {
+                height / total
+            } else {
+                biome.height.apply(value, self.noise_data.height_scale())
+            },
+            temperature,
+            humidity,
+            sea_level: self.noise_data.sea_level(),
+            roll: column_roll(self.seed, x, z),
+        }
+    }
+}
+
+/// Hashes a column into a value between 0 and 1.
+fn column_roll(seed: u32, x: i32, z: i32) -> f64 {
+    let mut hash = ((seed as u64) << 32) ^ ((x as u32 as u64) << 16) ^ z as u32 as u64;
+
+    // splitmix64 finalizer
+    hash = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^= hash >> 31;
+
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+pub struct BiomePlugin;
+
+impl BiomePlugin {
+    pub fn init_biomes(mut biomes: ResMut<Biomes>, materials: Res<Materials>) {
+        if let Some(loaded) = load_asset(BIOMES_PATH, "biomes", |source| {
+            Biomes::from_ron(source, &materials)
+        }) {
+            *biomes = loaded;
+        }
+    }
+}
+
+impl Plugin for BiomePlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<Biomes>()
+            .add_startup_system(Self::init_biomes.after(MaterialPlugin::init_materials));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{column_roll, BiomeError, Biomes};
+    use crate::{material::Materials, terrain::noise::NoiseData};
+
+    #[test]
+    pub fn default_biomes_test() {
+        let biomes = Biomes::default();
+        let desert = biomes.iter().find(|biome| biome.name == "desert").unwrap();
+
+        assert_eq!(desert.surface, 4);
+        assert_eq!(desert.filler_depth, 6);
+        assert!(desert.decoration(0.0).is_none());
+
+        let plains = biomes.iter().find(|biome| biome.name == "plains").unwrap();
+
+        assert_eq!(plains.decoration(0.001), Some(2));
+        assert_eq!(plains.decoration(0.5), None);
+
+        let unknown = Biomes::from_ron(
+            r#"[(name: "moon", temperature: 0.0, humidity: 0.0, surface: "cheese",
+                filler: "stone", height: (base: 0.0, amplitude: 1.0))]"#,
+            &Materials::default(),
+        );
+
+        assert!(matches!(unknown, Err(BiomeError::UnknownBlock(name)) if name == "cheese"));
+        assert!(matches!(
+            Biomes::from_ron("[]", &Materials::default()),
+            Err(BiomeError::Empty)
+        ));
+    }
+
+    #[test]
+    pub fn biome_blending_test() {
+        let biomes = Biomes::default();
+        let noise_data = NoiseData::new();
+        let sampler = biomes.sampler(&noise_data, 5);
+        let mut seen = Vec::new();
+
+        // walk far enough to cross several biome borders without the height ever jumping
+        let mut previous = sampler.sample(0, 0);
+
+        for x in 1..8192 {
+            let context = sampler.sample(x, 0);
+
+            assert!(
+                (context.height - previous.height).abs() < 4.0,
+                "height jumps at x = {x}"
+            );
+
+            if !seen.contains(&context.biome.name) {
+                seen.push(context.biome.name.clone());
+            }
+
+            previous = context;
+        }
+
+        assert!(seen.len() > 1, "only saw {seen:?}");
+    }
+
+    #[test]
+    pub fn column_roll_test() {
+        let rolls = (0..1000).map(|x| column_roll(3, x, -x)).collect::<Vec<_>>();
+
+        assert!(rolls.iter().all(|roll| (0.0..1.0).contains(roll)));
+        assert_eq!(rolls[10], column_roll(3, 10, -10));
+        assert_ne!(rolls[10], column_roll(4, 10, -10));
+    }
+}
@@ -0,0 +1,544 @@
+use std::{fmt::Display, sync::Arc};
+
+use bevy::{
+    log::error,
+    prelude::{IVec3, IntoSystemDescriptor, Plugin, Res, ResMut, Resource},
+    utils::HashMap,
+};
+use ndshape::ConstShape;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    asset::load_asset,
+    material::{MaterialPlugin, Materials},
+};
+
+use super::{biome::BiomeSampler, noise::DensitySampler};
+
+/// Trees, boulders and prefabs scattered over the terrain, with the biomes they grow in and how
+/// densely.
+pub const FEATURES_PATH: &str = "assets/features.ron";
+
+/// Holds a `<name>.ron` layout for every prefab named in [`FEATURES_PATH`].
+pub const STRUCTURES_PATH: &str = "assets/structures";
+
+/// Built-in copy of [`FEATURES_PATH`]. Unlike biomes, features have no default resource, so
+/// [`FeaturePlugin::init_features`] parses it whenever the file doesn't load.
+const DEFAULT_FEATURES: &str = include_str!("../../assets/features.ron");
+
+/// Built-in prefab layouts, by name, for [`DEFAULT_FEATURES`].
+const DEFAULT_STRUCTURES: &[(&str, &str)] =
+    &[("hut", include_str!("../../assets/structures/hut.ron"))];
+
+/// What a feature is built out of, with block ids resolved.
+#[derive(Clone, Debug)]
+pub enum Structure {
+    /// A trunk between `height.0` and `height.1` blocks tall, topped with a ball of leaves.
+    Tree {
+        trunk: u8,
+        leaves: u8,
+        height: (i32, i32),
+        radius: i32,
+    },
+    /// A ball of `block`, half buried in the ground.
+    Boulder { block: u8, radius: (i32, i32) },
+    /// Fixed blocks, as offsets from the anchor.
+    Prefab(Vec<(IVec3, u8)>),
+}
+
+impl Structure {
+    /// Blocks of one instance of the structure as offsets from its anchor, `rng` picks its size.
+    pub fn blocks(&self, rng: &mut StdRng) -> Vec<(IVec3, u8)> {
+        match self {
+            Self::Tree {
+                trunk,
+                leaves,
+                height,
+                radius,
+            } => {
+                let height = rng.gen_range(height.0..=height.1);
+                let top = IVec3::new(0, height, 0);
+                let mut blocks = ball(top, *radius, *leaves)
+                    .filter(|(offset, _)| offset.x != 0 || offset.z != 0 || offset.y > height)
+                    .collect::<Vec<_>>();
+
+                blocks.extend((0..=height).map(|y| (IVec3::new(0, y, 0), *trunk)));
+                blocks
+            }
+            Self::Boulder { block, radius } => {
+                ball(IVec3::ZERO, rng.gen_range(radius.0..=radius.1), *block).collect()
+            }
+            Self::Prefab(blocks) => blocks.clone(),
+        }
+    }
+}
+
+fn ball(center: IVec3, radius: i32, block: u8) -> impl Iterator<Item = (IVec3, u8)> {
+    (-radius..=radius)
+        .flat_map(move |z| {
+            (-radius..=radius)
+                .flat_map(move |y| (-radius..=radius).map(move |x| IVec3::new(x, y, z)))
+        })
+        .filter(move |offset| offset.dot(*offset) <= radius * radius + 1)
+        .map(move |offset| (center + offset, block))
+}
+
+#[derive(Clone, Debug)]
+pub struct Feature {
+    pub name: String,
+    pub structure: Structure,
+    /// Names of the biomes the feature spawns in, every biome if empty.
+    pub biomes: Vec<String>,
+    pub spacing: i32,
+    pub chance: f64,
+}
+
+#[derive(Deserialize)]
+enum StructureDefinition {
+    Tree {
+        trunk: String,
+        leaves: String,
+        height: (i32, i32),
+        radius: i32,
+    },
+    Boulder {
+        block: String,
+        radius: (i32, i32),
+    },
+    Prefab(String),
+}
+
+#[derive(Deserialize)]
+struct FeatureDefinition {
+    name: String,
+    structure: StructureDefinition,
+    #[serde(default)]
+    biomes: Vec<String>,
+    spacing: i32,
+    chance: f64,
+}
+
+#[derive(Deserialize)]
+struct PrefabDefinition {
+    palette: HashMap<char, String>,
+    anchor: (i32, i32, i32),
+    layers: Vec<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum FeatureError {
+    Parse(ron::error::SpannedError),
+    UnknownBlock(String),
+    UnknownPaletteEntry(char),
+    MissingStructure(String, std::io::Error),
+    InvalidSpacing(String),
+    /// A size range whose bounds are swapped or negative.
+    InvalidSize(String),
+}
+
+impl Display for FeatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(error) => write!(f, "{error}"),
+            Self::UnknownBlock(name) => write!(f, "no material is named {name:?}"),
+            Self::UnknownPaletteEntry(symbol) => write!(f, "{symbol:?} is not in the palette"),
+            Self::MissingStructure(name, error) => {
+                write!(f, "failed to read structure {name:?}: {error}")
+            }
+            Self::InvalidSpacing(name) => write!(f, "feature {name:?} needs a positive spacing"),
+            Self::InvalidSize(name) => {
+                write!(
+                    f,
+                    "feature {name:?} needs sizes of at least 0, from low to high"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FeatureError {}
+
+/// Every feature in placement order. Generation tasks each hold a clone, the list itself sits
+/// behind an `Arc`.
+#[derive(Resource, Clone, Default)]
+pub struct Features {
+    features: Arc<Vec<Feature>>,
+}
+
+impl Features {
+    /// The features shipped with the game, without touching the file system.
+    pub fn embedded(materials: &Materials) -> Result<Self, FeatureError> {
+        Self::from_ron(DEFAULT_FEATURES, materials, |name| {
+            DEFAULT_STRUCTURES
+                .iter()
+                .find(|(structure, _)| *structure == name)
+                .map(|(_, source)| source.to_string())
+                .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+        })
+    }
+
+    /// Parses feature definitions, resolving block names through `materials` and reading prefabs
+    /// through `load_structure`.
+    pub fn from_ron(
+        source: &str,
+        materials: &Materials,
+        load_structure: impl Fn(&str) -> std::io::Result<String>,
+    ) -> Result<Self, FeatureError> {
+        let definitions =
+            ron::from_str::<Vec<FeatureDefinition>>(source).map_err(FeatureError::Parse)?;
+
+        let block = |name: &str| {
+            materials
+                .id_of(name)
+                .ok_or_else(|| FeatureError::UnknownBlock(name.into()))
+        };
+
+        let features = definitions
+            .into_iter()
+            .map(|definition| {
+                if definition.spacing <= 0 {
+                    return Err(FeatureError::InvalidSpacing(definition.name));
+                }
+
+                // sizes are picked with `gen_range` inside generation tasks, which panics on
+                // an empty range
+                let valid_range = |(low, high): (i32, i32)| 0 <= low && low <= high;
+                let valid_size = match &definition.structure {
+                    StructureDefinition::Tree { height, radius, .. } => {
+                        valid_range(*height) && *radius >= 0
+                    }
+                    StructureDefinition::Boulder { radius, .. } => valid_range(*radius),
+                    StructureDefinition::Prefab(_) => true,
+                };
+
+                if !valid_size {
+                    return Err(FeatureError::InvalidSize(definition.name));
+                }
+
+                let structure = match definition.structure {
+                    StructureDefinition::Tree {
+                        trunk,
+                        leaves,
+                        height,
+                        radius,
+                    } => Structure::Tree {
+                        trunk: block(&trunk)?,
+                        leaves: block(&leaves)?,
+                        height,
+                        radius,
+                    },
+                    StructureDefinition::Boulder {
+                        block: name,
+                        radius,
+                    } => Structure::Boulder {
+                        block: block(&name)?,
+                        radius,
+                    },
+                    StructureDefinition::Prefab(name) => {
+                        let source = load_structure(&name)
+                            .map_err(|error| FeatureError::MissingStructure(name, error))?;
+
+                        Structure::Prefab(parse_prefab(&source, materials)?)
+                    }
+                };
+
+                Ok(Feature {
+                    name: definition.name,
+                    structure,
+                    biomes: definition.biomes,
+                    spacing: definition.spacing,
+                    chance: definition.chance,
+                })
+            })
+            .collect::<Result<Vec<_>, FeatureError>>()?;
+
+        Ok(Self {
+            features: Arc::new(features),
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Feature> {
+        self.features.iter()
+    }
+
+    /// Places every feature anchored inside the chunk whose minimum corner sits at `origin` into
+    /// its `blocks`, laid out the same way as `T`.
+    ///
+    /// Features stand on the highest ground block of their column, found through `density` so
+    /// every chunk agrees on it, even where the column's top lies in another chunk.
+    ///
+    /// Blocks that fall outside of the chunk are returned with their world position instead, to
+    /// be written into the chunks they belong to.
+    pub fn place<T: ConstShape<3, Coord = u32>>(
+        &self,
+        sampler: &BiomeSampler,
+        density: &DensitySampler,
+        seed: u32,
+        origin: [i32; 3],
+        blocks: &mut [u8],
+    ) -> Vec<(IVec3, u8)> {
+        let origin = IVec3::from(origin);
+        let size = IVec3::from(T::ARRAY.map(|axis| axis as i32));
+        let mut spill = Vec::new();
+
+        for (index, feature) in self.features.iter().enumerate() {
+            let spacing = feature.spacing;
+            // cells overlapping the chunk along x and z
+            let min = [origin.x, origin.z].map(|v| v.div_euclid(spacing));
+            let max = [origin.x + size.x - 1, origin.z + size.z - 1].map(|v| v.div_euclid(spacing));
+
+            for cell_z in min[1]..=max[1] {
+                for cell_x in min[0]..=max[0] {
+                    let mut rng =
+                        StdRng::seed_from_u64(cell_seed(seed, index as u32, cell_x, cell_z));
+
+                    if !rng.gen_bool(feature.chance.clamp(0.0, 1.0)) {
+                        continue;
+                    }
+
+                    let x = cell_x * spacing + rng.gen_range(0..spacing);
+                    let z = cell_z * spacing + rng.gen_range(0..spacing);
+
+                    // every chunk sees the same cells, only the one holding the anchor places it
+                    if !(origin.x..origin.x + size.x).contains(&x)
+                        || !(origin.z..origin.z + size.z).contains(&z)
+                    {
+                        continue;
+                    }
+
+                    let context = sampler.sample(x, z);
+
+                    if !feature.biomes.is_empty() && !feature.biomes.contains(&context.biome.name) {
+                        continue;
+                    }
+
+                    let Some(ground) = density.top_ground([x, z], &context) else {
+                        continue;
+                    };
+
+                    let anchor = IVec3::new(x, ground + 1, z);
+
+                    if !(origin.y..origin.y + size.y).contains(&anchor.y) {
+                        continue;
+                    }
+
+                    for (offset, block) in feature.structure.blocks(&mut rng) {
+                        let position = anchor + offset;
+                        let local = position - origin;
+
+                        if local.cmpge(IVec3::ZERO).all() && local.cmplt(size).all() {
+                            blocks[T::linearize(local.as_uvec3().to_array()) as usize] = block;
+                        } else {
+                            spill.push((position, block));
+                        }
+                    }
+                }
+            }
+        }
+
+        spill
+    }
+}
+
+fn parse_prefab(source: &str, materials: &Materials) -> Result<Vec<(IVec3, u8)>, FeatureError> {
+    let prefab = ron::from_str::<PrefabDefinition>(source).map_err(FeatureError::Parse)?;
+    let anchor = IVec3::new(prefab.anchor.0, prefab.anchor.1, prefab.anchor.2);
+    let mut blocks = Vec::new();
+
+    for (y, layer) in prefab.layers.iter().enumerate() {
+        for (z, row) in layer.iter().enumerate() {
+            for (x, symbol) in row.chars().enumerate() {
+                if symbol == ' ' {
+                    continue;
+                }
+
+                let name = prefab
+                    .palette
+                    .get(&symbol)
+                    .ok_or(FeatureError::UnknownPaletteEntry(symbol))?;
+                let block = materials
+                    .id_of(name)
+                    .ok_or_else(|| FeatureError::UnknownBlock(name.clone()))?;
+
+                blocks.push((IVec3::new(x as i32, y as i32, z as i32) - anchor, block));
+            }
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// Mixes the world seed, feature and cell into the seed of the cell's random generator.
+fn cell_seed(seed: u32, feature: u32, x: i32, z: i32) -> u64 {
+    [feature, x as u32, z as u32]
+        .into_iter()
+        .fold(seed as u64, |hash, value| {
+            (hash ^ value as u64)
+                .wrapping_mul(0x0100_0000_01b3)
+                .rotate_left(31)
+        })
+}
+
+pub struct FeaturePlugin;
+
+impl FeaturePlugin {
+    pub fn init_features(mut features: ResMut<Features>, materials: Res<Materials>) {
+        let embedded = || {
+            Features::embedded(&materials).unwrap_or_else(|error| {
+                error!("failed to load the default features: {error}");
+                Features::default()
+            })
+        };
+
+        let load_structure =
+            |name: &str| std::fs::read_to_string(format!("{STRUCTURES_PATH}/{name}.ron"));
+
+        *features = load_asset(FEATURES_PATH, "features", |source| {
+            Features::from_ron(source, &materials, load_structure)
+        })
+        .unwrap_or_else(embedded);
+    }
+}
+
+impl Plugin for FeaturePlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<Features>()
+            .add_startup_system(Self::init_features.after(MaterialPlugin::init_materials));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::IVec3;
+    use ndshape::ConstShape;
+
+    use super::{FeatureError, Features, Structure};
+    use crate::{
+        chunk::ChunkShape,
+        material::Materials,
+        terrain::{
+            biome::Biomes,
+            noise::{DensitySampler, NoiseData},
+        },
+    };
+
+    #[test]
+    pub fn default_features_test() {
+        let materials = Materials::default();
+        let features = Features::embedded(&materials).unwrap();
+        let hut = features
+            .iter()
+            .find(|feature| feature.name == "hut")
+            .unwrap();
+
+        let Structure::Prefab(blocks) = &hut.structure else {
+            panic!("the hut is a prefab");
+        };
+
+        // the floor sits one block below the anchor, spaces and the door are left out or carved
+        assert!(blocks.contains(&(IVec3::new(-2, -1, -2), 4)));
+        assert!(blocks.contains(&(IVec3::new(2, 0, 0), 0)));
+        assert_eq!(blocks.len(), 5 * 5 * 4);
+
+        let missing = Features::from_ron(
+            r#"[(name: "castle", structure: Prefab("castle"), spacing: 64, chance: 1.0)]"#,
+            &materials,
+            |_| Err(std::io::ErrorKind::NotFound.into()),
+        );
+
+        assert!(
+            matches!(missing, Err(FeatureError::MissingStructure(name, _)) if name == "castle")
+        );
+
+        let reversed = Features::from_ron(
+            r#"[(name: "rock", structure: Boulder(block: "stone", radius: (3, 1)), spacing: 16,
+                chance: 0.5)]"#,
+            &materials,
+            |_| unreachable!(),
+        );
+
+        assert!(matches!(reversed, Err(FeatureError::InvalidSize(name)) if name == "rock"));
+    }
+
+    #[test]
+    pub fn feature_border_test() {
+        type WideShape = ndshape::ConstShape3u32<96, 32, 96>;
+
+        let materials = Materials::default();
+        let features = Features::from_ron(
+            r#"[(name: "tree", structure: Tree(trunk: "log", leaves: "leaves", height: (4, 6),
+                radius: 2), spacing: 6, chance: 0.8)]"#,
+            &materials,
+            |_| unreachable!(),
+        )
+        .unwrap();
+        let biomes = Biomes::default();
+        let noise_data = NoiseData::new();
+        let sampler = biomes.sampler(&noise_data, 21);
+        let density = DensitySampler::new(&noise_data, 21);
+
+        // find a layer of chunks the trees grow in
+        let y = density
+            .top_ground([48, 48], &sampler.sample(48, 48))
+            .unwrap()
+            .div_euclid(32)
+            * 32;
+
+        // placing a wide area at once matches placing its chunks and writing what they spill
+        let mut wide = vec![0; WideShape::SIZE as usize];
+
+        for (position, _) in
+            features.place::<WideShape>(&sampler, &density, 21, [0, y, 0], &mut wide)
+        {
+            let local = position - IVec3::new(0, y, 0);
+
+            if local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::new(96, 32, 96)).all() {
+                panic!("{position} is inside the area but was spilled");
+            }
+        }
+
+        let mut chunked = vec![0; WideShape::SIZE as usize];
+        let mut spilled = Vec::new();
+
+        for chunk_z in 0..3 {
+            for chunk_x in 0..3 {
+                let origin = [chunk_x * 32, y, chunk_z * 32];
+                let mut blocks = vec![0; ChunkShape::SIZE as usize];
+
+                spilled.extend(features.place::<ChunkShape>(
+                    &sampler,
+                    &density,
+                    21,
+                    origin,
+                    &mut blocks,
+                ));
+
+                for i in 0..ChunkShape::SIZE {
+                    let [x, y, z] = ChunkShape::delinearize(i);
+                    let index =
+                        WideShape::linearize([x + origin[0] as u32, y, z + origin[2] as u32]);
+
+                    if blocks[i as usize] != 0 {
+                        chunked[index as usize] = blocks[i as usize];
+                    }
+                }
+            }
+        }
+
+        for (position, block) in spilled {
+            let local = position - IVec3::new(0, y, 0);
+
+            if local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::new(96, 32, 96)).all() {
+                chunked[WideShape::linearize(local.as_uvec3().to_array()) as usize] = block;
+            }
+        }
+
+        assert!(wide.contains(&7));
+
+        for i in 0..WideShape::SIZE as usize {
+            // where trees overlap, which one wins depends on the order blocks are written in
+            assert_eq!(wide[i] != 0, chunked[i] != 0);
+        }
+    }
+}
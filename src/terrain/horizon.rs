@@ -0,0 +1,458 @@
+use bevy::{
+    prelude::{
+        Assets, Camera, Commands, Entity, Handle, IVec2, Mesh, PbrBundle, Plugin, Query, Res,
+        ResMut, Resource, StandardMaterial, Transform, Vec3, With,
+    },
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_resource::PrimitiveTopology,
+    },
+    tasks::{AsyncComputeTaskPool, Task},
+    utils::{HashMap, HashSet},
+};
+use futures_lite::future;
+use height_mesh::{
+    height_mesh,
+    ndshape::{ConstShape, ConstShape2u32},
+    HeightMeshBuffer,
+};
+
+use crate::{
+    camera::RENDER_DISTANCE,
+    chunk::{
+        container::{loaded::LoadedChunks, ChunkKey, Chunks, DomainChunk},
+        X_SIZE, Y_SIZE, Z_SIZE,
+    },
+    material::Materials,
+};
+
+use super::{
+    biome::Biomes,
+    noise::{NoiseData, WorldSeed},
+    BiomeTerrainGenerator, TerrainGenerator,
+};
+
+/// Blocks along each side of a horizon tile.
+pub const TILE_SIZE: i32 = 512;
+/// Blocks between two height samples, divides the chunk width so cells line up with chunk columns.
+pub const TILE_STEP: i32 = 8;
+
+const TILE_CELLS: u32 = (TILE_SIZE / TILE_STEP) as u32;
+
+/// Height samples of a tile, with one sample of padding on every side for the normals.
+type TileShape = ConstShape2u32<{ TILE_CELLS + 3 }, { TILE_CELLS + 3 }>;
+
+/// Position of a horizon tile, i.e. world block `(x, z)` divided by [`TILE_SIZE`].
+pub type TileKey = IVec2;
+
+/// Heightmap mesh of a horizon tile, positioned relative to the tile's minimum corner.
+pub struct TileMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl TileMesh {
+    /// Samples the same noise the voxel terrain is generated from, so both surfaces line up.
+    pub fn generate(
+        noise_data: &NoiseData,
+        seed: u32,
+        biomes: &Biomes,
+        terrain: &impl TerrainGenerator,
+        materials: &Materials,
+        tile: TileKey,
+    ) -> Self {
+        let sampler = biomes.sampler(noise_data, seed);
+        let origin = tile * TILE_SIZE;
+        let mut heights = vec![0.0; TileShape::SIZE as usize];
+        let mut sample_colors = vec![[0.0; 4]; TileShape::SIZE as usize];
+
+        for i in 0..TileShape::SIZE {
+            let [x, z] = TileShape::delinearize(i);
+            // sample 1 sits on the minimum corner of the tile, sample 0 is padding
+            let world = origin + (IVec2::new(x as i32, z as i32) - 1) * TILE_STEP;
+            let context = sampler.sample(world.x, world.y);
+            // oceans are drawn at the height of their water
+            let surface = (context.height.floor() as i32).max(context.sea_level);
+            let block = terrain.get_block_type([world.x, surface, world.y], &context);
+            let [r, g, b, _] = materials.get_from_id(block).rgba();
+
+            // voxel terrain fills the block at the surface height, so its top is one block higher
+            heights[i as usize] = surface as f32 + 1.0;
+            sample_colors[i as usize] = [r, g, b, 1.0];
+        }
+
+        let mut buffer = HeightMeshBuffer::default();
+
+        height_mesh(
+            &heights,
+            &TileShape {},
+            [0; 2],
+            [TILE_CELLS + 2; 2],
+            &mut buffer,
+        );
+
+        let step = TILE_STEP as f32;
+
+        Self {
+            colors: buffer
+                .positions
+                .iter()
+                .map(|[x, _, z]| {
+                    sample_colors[TileShape::linearize([*x as u32, *z as u32]) as usize]
+                })
+                .collect(),
+            positions: buffer
+                .positions
+                .into_iter()
+                .map(|[x, y, z]| [(x - 1.0) * step, y, (z - 1.0) * step])
+                .collect(),
+            // the slopes were estimated with one block between samples
+            normals: buffer
+                .normals
+                .into_iter()
+                .map(|[x, y, z]| {
+                    Vec3::new(x / step, y, z / step)
+                        .normalize_or_zero()
+                        .to_array()
+                })
+                .collect(),
+            indices: buffer.indices,
+        }
+    }
+
+    /// Indices of the triangles whose surface doesn't lie inside chunks `loaded` returns true
+    /// for, so the voxel terrain drawing it takes over.
+    pub fn visible_indices(&self, tile: TileKey, loaded: impl Fn(ChunkKey) -> bool) -> Vec<u32> {
+        let chunk_size = IVec2::new(X_SIZE as i32, Z_SIZE as i32);
+
+        self.indices
+            .chunks_exact(3)
+            .filter(|triangle| {
+                let corners = triangle.iter().map(|index| self.positions[*index as usize]);
+                let cell = corners
+                    .clone()
+                    .map(|[x, _, z]| IVec2::new(x as i32, z as i32))
+                    .reduce(IVec2::min)
+                    .unwrap();
+                let column = div_euclid(tile * TILE_SIZE + cell, chunk_size);
+
+                // the top block of the voxel terrain sits one below the heightmap
+                let [low, high] = [f32::min, f32::max].map(|pick| {
+                    let top = corners.clone().map(|[_, y, _]| y).reduce(pick).unwrap() - 1.0;
+
+                    (top.floor() as i32).div_euclid(Y_SIZE as i32)
+                });
+
+                !(low..=high).all(|y| loaded(ChunkKey::new(column.x, y, column.y)))
+            })
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    pub fn to_mesh(&self, indices: Vec<u32>) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+        for (key, value) in [
+            (
+                Mesh::ATTRIBUTE_POSITION,
+                VertexAttributeValues::Float32x3(self.positions.clone()),
+            ),
+            (
+                Mesh::ATTRIBUTE_NORMAL,
+                VertexAttributeValues::Float32x3(self.normals.clone()),
+            ),
+            (
+                Mesh::ATTRIBUTE_COLOR,
+                VertexAttributeValues::Float32x4(self.colors.clone()),
+            ),
+        ] {
+            mesh.insert_attribute(key, value);
+        }
+
+        mesh.set_indices(Some(Indices::U32(indices)));
+        mesh
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct HorizonSettings {
+    /// Tiles drawn around the camera along the x and z axes.
+    pub radius: i32,
+}
+
+impl Default for HorizonSettings {
+    fn default() -> Self {
+        Self { radius: 8 }
+    }
+}
+
+struct HorizonTile {
+    data: TileMesh,
+    entity: Entity,
+    mesh: Handle<Mesh>,
+}
+
+/// Far terrain drawn as heightmap tiles in a ring around the voxel render distance.
+///
+/// Cells whose surface lies in chunks that are loaded as voxels are left out of the tile meshes,
+/// so the voxel terrain takes over without the two overlapping or leaving a hole.
+#[derive(Resource, Default)]
+pub struct Horizon {
+    tiles: HashMap<TileKey, HorizonTile>,
+    tasks: HashMap<TileKey, Task<TileMesh>>,
+    /// Tiles with cells currently left out for voxel terrain.
+    cut: HashSet<TileKey>,
+    center: Option<TileKey>,
+    material: Option<Handle<StandardMaterial>>,
+}
+
+pub struct HorizonPlugin;
+
+impl HorizonPlugin {
+    #[allow(clippy::too_many_arguments)]
+    pub fn update_tiles(
+        mut commands: Commands,
+        mut horizon: ResMut<Horizon>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut bevy_materials: ResMut<Assets<StandardMaterial>>,
+        settings: Res<HorizonSettings>,
+        loaded_chunks: Res<LoadedChunks>,
+        noise_data: Res<NoiseData>,
+        world_seed: Res<WorldSeed>,
+        biomes: Res<Biomes>,
+        materials: Res<Materials>,
+        query: Query<&Transform, With<Camera>>,
+    ) {
+        let Ok(transform) = query.get_single() else {
+            return;
+        };
+
+        let horizon = horizon.as_mut();
+        let translation = transform.translation.floor().as_ivec3();
+        let center = div_euclid(
+            IVec2::new(translation.x, translation.z),
+            IVec2::splat(TILE_SIZE),
+        );
+        let in_range = |tile: TileKey| (tile - center).abs().max_element() <= settings.radius;
+
+        if horizon.center != Some(center) {
+            horizon.tiles.retain(|tile, HorizonTile { entity, .. }| {
+                in_range(*tile) || {
+                    commands.entity(*entity).despawn();
+                    false
+                }
+            });
+            // dropping a task cancels it
+            horizon.tasks.retain(|tile, _| in_range(*tile));
+            horizon.cut.retain(|tile| in_range(*tile));
+
+            let pool = AsyncComputeTaskPool::get();
+            let generator = BiomeTerrainGenerator::from_materials(&materials);
+
+            for x in -settings.radius..=settings.radius {
+                for z in -settings.radius..=settings.radius {
+                    let tile = center + IVec2::new(x, z);
+
+                    if horizon.tiles.contains_key(&tile) || horizon.tasks.contains_key(&tile) {
+                        continue;
+                    }
+
+                    let noise_data = noise_data.as_ref().clone();
+                    let seed = world_seed.0;
+                    let materials = materials.as_ref().clone();
+                    let biomes = biomes.as_ref().clone();
+
+                    horizon.tasks.insert(
+                        tile,
+                        pool.spawn(async move {
+                            TileMesh::generate(
+                                &noise_data,
+                                seed,
+                                &biomes,
+                                &generator,
+                                &materials,
+                                tile,
+                            )
+                        }),
+                    );
+                }
+            }
+
+            horizon.center = Some(center);
+        }
+
+        let camera_chunk = ChunkKey::from(Chunks::domain_of(translation.to_array()));
+        let loaded = |key: ChunkKey| loaded_chunks.is_chunk_id_loaded(&key);
+
+        let finished = horizon
+            .tasks
+            .iter_mut()
+            .filter_map(|(tile, task)| {
+                future::block_on(future::poll_once(task)).map(|data| (*tile, data))
+            })
+            .collect::<Vec<_>>();
+
+        let material = horizon
+            .material
+            .get_or_insert_with(|| {
+                bevy_materials.add(StandardMaterial {
+                    perceptual_roughness: 0.47,
+                    ..Default::default()
+                })
+            })
+            .clone();
+
+        for (tile, data) in finished {
+            horizon.tasks.remove(&tile);
+
+            let mesh = meshes.add(data.to_mesh(data.visible_indices(tile, loaded)));
+            let origin = tile * TILE_SIZE;
+            let entity = commands
+                .spawn(PbrBundle {
+                    mesh: mesh.clone(),
+                    material: material.clone(),
+                    transform: Transform::from_xyz(origin.x as f32, 0.0, origin.y as f32),
+                    ..Default::default()
+                })
+                .id();
+
+            horizon.cut.insert(tile);
+            horizon
+                .tiles
+                .insert(tile, HorizonTile { data, entity, mesh });
+        }
+
+        if !loaded_chunks.is_changed() {
+            return;
+        }
+
+        // only tiles that overlap the voxel render distance, or did so before, can change
+        let chunk_size = IVec2::new(X_SIZE as i32, Z_SIZE as i32);
+        let column = IVec2::new(camera_chunk.x, camera_chunk.z);
+        let min = div_euclid(
+            (column - RENDER_DISTANCE) * chunk_size,
+            IVec2::splat(TILE_SIZE),
+        );
+        let max = div_euclid(
+            (column + RENDER_DISTANCE + 1) * chunk_size - 1,
+            IVec2::splat(TILE_SIZE),
+        );
+        let overlapping = (min.x..=max.x)
+            .flat_map(|x| (min.y..=max.y).map(move |z| IVec2::new(x, z)))
+            .collect::<HashSet<_>>();
+
+        for tile in horizon.cut.union(&overlapping) {
+            let Some(HorizonTile { data, mesh, .. }) = horizon.tiles.get(tile) else {
+                continue;
+            };
+
+            if let Some(mesh) = meshes.get_mut(mesh) {
+                mesh.set_indices(Some(Indices::U32(data.visible_indices(*tile, loaded))));
+            }
+        }
+
+        horizon.cut = overlapping;
+    }
+}
+
+fn div_euclid(a: IVec2, b: IVec2) -> IVec2 {
+    IVec2::new(a.x.div_euclid(b.x), a.y.div_euclid(b.y))
+}
+
+impl Plugin for HorizonPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.init_resource::<HorizonSettings>()
+            .init_resource::<Horizon>()
+            .add_system(Self::update_tiles);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{prelude::IVec2, utils::HashSet};
+
+    use super::{TileMesh, TILE_SIZE, TILE_STEP};
+    use crate::{
+        chunk::{X_SIZE, Y_SIZE, Z_SIZE},
+        material::Materials,
+        terrain::{biome::Biomes, noise::NoiseData, BiomeTerrainGenerator},
+    };
+
+    fn tile(key: IVec2) -> TileMesh {
+        TileMesh::generate(
+            &NoiseData::new(),
+            3,
+            &Biomes::default(),
+            &BiomeTerrainGenerator::default(),
+            &Materials::default(),
+            key,
+        )
+    }
+
+    #[test]
+    pub fn tile_seam_test() {
+        let left = tile(IVec2::new(-1, 2));
+        let right = tile(IVec2::new(0, 2));
+
+        let edge = |mesh: &TileMesh, x: f32| {
+            let mut edge = mesh
+                .positions
+                .iter()
+                .filter(|position| position[0] == x)
+                .map(|[_, y, z]| [*y, *z])
+                .collect::<Vec<_>>();
+
+            edge.sort_by(|a, b| a[1].total_cmp(&b[1]));
+            edge
+        };
+
+        let shared = edge(&left, TILE_SIZE as f32);
+
+        assert_eq!(shared.len() as i32, TILE_SIZE / TILE_STEP + 1);
+        assert_eq!(shared, edge(&right, 0.0));
+    }
+
+    #[test]
+    pub fn hidden_columns_test() {
+        let key = IVec2::new(1, -1);
+        let mesh = tile(key);
+        let cells = (TILE_SIZE / TILE_STEP) as usize;
+
+        assert_eq!(
+            mesh.visible_indices(key, |_| false).len(),
+            cells * cells * 6
+        );
+        assert!(mesh.visible_indices(key, |_| true).is_empty());
+
+        // every chunk column covers a square of cells
+        let column = key * TILE_SIZE / IVec2::new(X_SIZE as i32, Z_SIZE as i32);
+        let cells_per_column = X_SIZE / TILE_STEP as usize * Z_SIZE / TILE_STEP as usize;
+
+        assert_eq!(
+            mesh.visible_indices(key, |loaded| IVec2::new(loaded.x, loaded.z) == column)
+                .len(),
+            (cells * cells - cells_per_column) * 6
+        );
+
+        // chunks far above or below the surface don't draw it, so nothing is cut for them
+        assert_eq!(
+            mesh.visible_indices(key, |loaded| loaded.y.abs() >= 100)
+                .len(),
+            cells * cells * 6
+        );
+
+        // the column is cut once the chunks its surface lies in are there, whatever else isn't
+        let surface = mesh
+            .positions
+            .iter()
+            .map(|[_, y, _]| ((y - 1.0).floor() as i32).div_euclid(Y_SIZE as i32))
+            .collect::<HashSet<_>>();
+
+        assert!(mesh
+            .visible_indices(key, |loaded| surface.contains(&loaded.y))
+            .is_empty());
+    }
+}
@@ -1,19 +1,99 @@
+use crate::material::{Material, Materials, Stone, Water};
+
+use self::biome::BiomeContext;
+
+pub mod biome;
+pub mod feature;
+pub mod horizon;
 pub mod noise;
+pub mod ore;
 
 pub trait TerrainGenerator {
-    fn get_block_type(&self, height: f64) -> u8;
+    /// Block id at the world position `[x, y, z]`, in the column described by `context`.
+    fn get_block_type(&self, position: [i32; 3], context: &BiomeContext) -> u8;
 }
 
-pub struct DebugTerrainGenerator;
+/// Builds every column out of its biome's surface and filler blocks on top of stone, with the
+/// biome's decorations on top and water filling everything up to sea level.
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeTerrainGenerator {
+    pub stone: u8,
+    pub water: u8,
+}
 
-impl TerrainGenerator for DebugTerrainGenerator {
-    fn get_block_type(&self, height: f64) -> u8 {
-        for (id, range) in [(1, 7..40), (2, 5..7), (3, 4..5), (4, 2..4), (3, 0..2)] {
-            if range.contains(&(height.ceil() as i32)) {
-                return id;
-            }
+impl Default for BiomeTerrainGenerator {
+    fn default() -> Self {
+        Self { stone: 2, water: 3 }
+    }
+}
+
+impl BiomeTerrainGenerator {
+    pub fn from_materials(materials: &Materials) -> Self {
+        let default = Self::default();
+        let id = |material: Option<&Material>, fallback| {
+            material.map_or(fallback, |material| material.id)
+        };
+
+        Self {
+            stone: id(materials.get::<Stone>(), default.stone),
+            water: id(materials.get::<Water>(), default.water),
         }
+    }
+}
+
+impl TerrainGenerator for BiomeTerrainGenerator {
+    fn get_block_type(&self, [_, y, _]: [i32; 3], context: &BiomeContext) -> u8 {
+        let surface = context.height.floor() as i32;
+        let biome = context.biome;
 
-        return 0;
+        if y > surface && y <= context.sea_level {
+            self.water
+        } else if y == surface + 1 {
+            biome.decoration(context.roll).unwrap_or(0)
+        } else if y > surface {
+            0
+        } else if y == surface {
+            biome.surface
+        } else if y > surface - biome.filler_depth {
+            biome.filler
+        } else {
+            self.stone
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{biome::Biomes, noise::NoiseData, BiomeTerrainGenerator, TerrainGenerator};
+
+    #[test]
+    pub fn biome_column_test() {
+        let biomes = Biomes::default();
+        let noise_data = NoiseData::new();
+        let sampler = biomes.sampler(&noise_data, 11);
+        let generator = BiomeTerrainGenerator::default();
+
+        for x in (0..4096).step_by(64) {
+            let context = sampler.sample(x, 0);
+            let surface = context.height.floor() as i32;
+            let biome = context.biome;
+            let block = |y| generator.get_block_type([x, y, 0], &context);
+
+            assert_eq!(block(surface), biome.surface);
+            assert_eq!(block(surface - biome.filler_depth + 1), biome.filler);
+            assert_eq!(block(surface - biome.filler_depth), generator.stone);
+
+            if surface < context.sea_level {
+                assert_eq!(block(surface + 1), generator.water);
+                assert_eq!(block(context.sea_level), generator.water);
+                assert_eq!(block(context.sea_level + 1), 0);
+            } else {
+                assert_eq!(block(surface + 2), 0);
+                assert_eq!(
+                    block(surface + 1),
+                    biome.decoration(context.roll).unwrap_or(0)
+                );
+            }
+        }
     }
 }
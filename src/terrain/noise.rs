@@ -3,12 +3,14 @@ use bevy::reflect::Reflect;
 use bevy_inspector_egui::InspectorOptions;
 use ndshape::ConstShape;
 use noise::utils::NoiseMap;
-use noise::utils::NoiseMapBuilder;
-use noise::utils::PlaneMapBuilder;
 use noise::Fbm;
 use noise::MultiFractal;
+use noise::NoiseFn;
 
-use super::TerrainGenerator;
+use super::{
+    biome::{BiomeContext, Biomes},
+    TerrainGenerator,
+};
 use bevy::ecs::reflect::ReflectResource;
 use bevy_inspector_egui::prelude::ReflectInspectorOptions;
 use noise::Perlin;
@@ -19,6 +21,24 @@ pub struct NoiseData {
     octaves: usize,
     persistence: f64,
     lacunarity: f64,
+    scale: f64,
+    height_scale: f64,
+    /// Highest world y filled with water where the terrain lies below it.
+    sea_level: i32,
+    /// Whether caves are carved out of the terrain at all.
+    caves: bool,
+    /// Frequency of the noise large caves are carved from.
+    cave_scale: f64,
+    /// Cave noise above this value is carved out, lower values make caves larger.
+    cave_threshold: f64,
+    /// Frequency of the two noises whose zero crossings meet in winding tunnels.
+    tunnel_scale: f64,
+    /// How close to zero both tunnel noises have to be, higher values make tunnels wider.
+    tunnel_width: f64,
+    /// Frequency of the noise that pushes the surface in and out to form overhangs.
+    overhang_scale: f64,
+    /// Blocks the surface gets pushed in or out by at most.
+    overhang_height: f64,
 }
 
 impl NoiseData {
@@ -27,52 +47,500 @@ impl NoiseData {
             octaves: 1,
             persistence: 3.512,
             lacunarity: 3.351,
+            scale: 1.0 / 16.0,
+            height_scale: 32.0,
+            sea_level: 0,
+            caves: true,
+            cave_scale: 1.0 / 48.0,
+            cave_threshold: 0.5,
+            tunnel_scale: 1.0 / 64.0,
+            tunnel_width: 0.05,
+            overhang_scale: 1.0 / 12.0,
+            overhang_height: 8.0,
         }
     }
+
+    /// Multiplier of every biome's height amplitude.
+    pub fn height_scale(&self) -> f64 {
+        self.height_scale
+    }
+
+    pub fn sea_level(&self) -> i32 {
+        self.sea_level
+    }
+}
+
+/// Blocks below the surface of columns under water that caves can't carve into.
+const SEA_FLOOR_DEPTH: i32 = 4;
+
+/// Blocks below the lowest possible surface [`DensitySampler::top_ground`] looks for ground
+/// in, before giving up on a column caves opened all the way down.
+const GROUND_SEARCH_DEPTH: i32 = 32;
+
+/// Samples the 3D noise that turns heightmap terrain into a density field, with overhangs
+/// where the surface gets pushed around and caves carved out below it.
+///
+/// Everything is sampled at world coordinates, so caves continue across chunk borders.
+pub struct DensitySampler<'a> {
+    noise_data: &'a NoiseData,
+    cave: Perlin,
+    tunnels: [Perlin; 2],
+    overhang: Perlin,
+}
+
+impl<'a> DensitySampler<'a> {
+    pub fn new(noise_data: &'a NoiseData, seed: u32) -> Self {
+        // offset from the seed so none of these line up with the height or climate noise
+        Self {
+            noise_data,
+            cave: Perlin::new(seed.wrapping_add(3)),
+            tunnels: [
+                Perlin::new(seed.wrapping_add(4)),
+                Perlin::new(seed.wrapping_add(5)),
+            ],
+            overhang: Perlin::new(seed.wrapping_add(6)),
+        }
+    }
+
+    /// Surface height of the column at `[x, y, z]` once overhangs pushed it up or down.
+    pub fn surface(&self, [x, y, z]: [i32; 3], height: f64) -> f64 {
+        let scale = self.noise_data.overhang_scale;
+        let offset = self
+            .overhang
+            .get([x as f64 * scale, y as f64 * scale, z as f64 * scale]);
+
+        height + offset * self.noise_data.overhang_height
+    }
+
+    /// Whether the block at `[x, y, z]` is inside a cave or tunnel.
+    pub fn carved(&self, [x, y, z]: [i32; 3]) -> bool {
+        if !self.noise_data.caves {
+            return false;
+        }
+
+        let sample = |noise: &Perlin, scale: f64| {
+            noise.get([x as f64 * scale, y as f64 * scale, z as f64 * scale])
+        };
+
+        let width = self.noise_data.tunnel_width;
+        let tunnel = self
+            .tunnels
+            .iter()
+            .all(|noise| sample(noise, self.noise_data.tunnel_scale).abs() < width);
+
+        tunnel || sample(&self.cave, self.noise_data.cave_scale) > self.noise_data.cave_threshold
+    }
+
+    /// Roughly how many blocks deep `[x, y, z]` lies inside a cave or tunnel, negative outside of
+    /// them. Only the sign matches [`DensitySampler::carved`] exactly, the distance is guessed
+    /// from the noise frequencies.
+    fn carve_depth(&self, [x, y, z]: [i32; 3]) -> f64 {
+        if !self.noise_data.caves {
+            return f64::NEG_INFINITY;
+        }
+
+        let sample = |noise: &Perlin, scale: f64| {
+            noise.get([x as f64 * scale, y as f64 * scale, z as f64 * scale])
+        };
+
+        let scale = self.noise_data.tunnel_scale;
+        let tunnel = self
+            .tunnels
+            .iter()
+            .map(|noise| sample(noise, scale).abs())
+            .fold(f64::NEG_INFINITY, f64::max);
+        let tunnel = (self.noise_data.tunnel_width - tunnel) / scale;
+
+        let scale = self.noise_data.cave_scale;
+        let cave = (sample(&self.cave, scale) - self.noise_data.cave_threshold) / scale;
+
+        tunnel.max(cave)
+    }
+
+    /// Signed distance in blocks from `position` to the ground, negative inside it, given the
+    /// `surface` of its column from [`DensitySampler::surface`]. The blocks
+    /// [`DensitySampler::is_ground`] accepts are the ones at or below zero.
+    pub fn density(&self, position: [i32; 3], surface: f64, sea_level: i32) -> f64 {
+        let above = position[1] as f64 - surface;
+        let top = surface.floor() as i32;
+
+        if position[1] > top || self.sea_floor(position, top, sea_level) {
+            return above;
+        }
+
+        above.max(self.carve_depth(position))
+    }
+
+    fn sea_floor(&self, position: [i32; 3], surface: i32, sea_level: i32) -> bool {
+        surface < sea_level && position[1] > surface - SEA_FLOOR_DEPTH
+    }
+
+    /// Whether the block at `position`, at or below the given `surface` of its column, is carved
+    /// out. The sea floor stays closed, so oceans don't hang over dry caves.
+    fn carved_below(&self, position: [i32; 3], surface: i32, sea_level: i32) -> bool {
+        !self.sea_floor(position, surface, sea_level) && self.carved(position)
+    }
+
+    /// Whether the block at `position` in `column` is solid ground, below the surface once
+    /// overhangs moved it and not carved out. Water and decorations don't count.
+    pub fn is_ground(&self, position: [i32; 3], column: &BiomeContext) -> bool {
+        let surface = self.surface(position, column.height).floor() as i32;
+
+        position[1] <= surface && !self.carved_below(position, surface, column.sea_level)
+    }
+
+    /// Highest ground block of `column` at `[x, z]`, or `None` if caves opened it up too deep.
+    pub fn top_ground(&self, [x, z]: [i32; 2], column: &BiomeContext) -> Option<i32> {
+        let reach = self.noise_data.overhang_height.abs();
+        // perlin noise can overshoot its range by a little
+        let top = (column.height + reach).floor() as i32 + 1;
+        let bottom = (column.height - reach).floor() as i32 - GROUND_SEARCH_DEPTH;
+
+        (bottom..=top)
+            .rev()
+            .find(|y| self.is_ground([x, *y, z], column))
+    }
+}
+
+/// The seed every chunk of the world is generated from.
+///
+/// Noise is sampled at absolute world coordinates, so the same seed always
+/// produces the same terrain, no matter in which order chunks are generated.
+#[derive(Reflect, Resource, Default, InspectorOptions, Clone, Copy)]
+#[reflect(Resource, InspectorOptions)]
+pub struct WorldSeed(pub u32);
+
+impl WorldSeed {
+    pub fn new(seed: u32) -> Self {
+        Self(seed)
+    }
 }
 
-pub fn generate_terrain_3d<T: ConstShape<3, Coord = u32>, N: ConstShape<2, Coord = usize>>(
+/// Generates the blocks of the chunk whose minimum corner sits at `origin`
+/// (in world block coordinates, `[x, y, z]`), laid out the same way as `T`.
+pub fn generate_terrain_3d<T: ConstShape<3, Coord = u32>>(
     noise_data: &NoiseData,
     seed: u32,
+    biomes: &Biomes,
+    origin: [i32; 3],
     terrain: impl TerrainGenerator,
 ) -> Vec<u8> {
-    let mut ids = Vec::new();
-    let noise_map = generate_noise_map::<N>(
-        seed,
-        noise_data.octaves,
-        noise_data.persistence,
-        noise_data.lacunarity,
-    );
-
-    for z in 0..T::ARRAY[2] {
-        for y in 0..T::ARRAY[1] {
-            for x in 0..T::ARRAY[0] {
-                // scale the noise value to fit the desired range of heights
-                let height = (noise_map[(x as usize, y as usize)] as f32) * T::ARRAY[2] as f32;
-                if z as f32 <= height {
-                    ids.push(terrain.get_block_type(height.into()));
+    generate_terrain_density_3d::<T>(noise_data, seed, biomes, origin, terrain).0
+}
+
+/// Like [`generate_terrain_3d`], but also returns the [`DensitySampler::density`] of every
+/// block, for meshing the ground smoothly.
+pub fn generate_terrain_density_3d<T: ConstShape<3, Coord = u32>>(
+    noise_data: &NoiseData,
+    seed: u32,
+    biomes: &Biomes,
+    [x, y, z]: [i32; 3],
+    terrain: impl TerrainGenerator,
+) -> (Vec<u8>, Vec<f32>) {
+    let mut ids = vec![0; T::SIZE as usize];
+    let mut densities = vec![0.0; T::SIZE as usize];
+    let sampler = biomes.sampler(noise_data, seed);
+    let density = DensitySampler::new(noise_data, seed);
+
+    for inner_z in 0..T::ARRAY[2] {
+        for inner_x in 0..T::ARRAY[0] {
+            let [world_x, world_z] = [x + inner_x as i32, z + inner_z as i32];
+            let column = sampler.sample(world_x, world_z);
+
+            for inner_y in 0..T::ARRAY[1] {
+                let position = [world_x, y + inner_y as i32, world_z];
+                let context = BiomeContext {
+                    height: density.surface(position, column.height),
+                    ..column
+                };
+
+                let mut block = terrain.get_block_type(position, &context);
+                let surface = context.height.floor() as i32;
+
+                let carved = if position[1] <= surface {
+                    density.carved_below(position, surface, context.sea_level)
                 } else {
-                    ids.push(0);
+                    // blocks above the surface, like decorations, fall into caves opened below
+                    // them, water stays where it is
+                    position[1] > context.sea_level
+                        && density.carved([world_x, position[1] - 1, world_z])
+                };
+
+                if block != 0 && carved {
+                    block = 0;
                 }
+
+                let index = T::linearize([inner_x, inner_y, inner_z]) as usize;
+
+                ids[index] = block;
+                densities[index] =
+                    density.density(position, context.height, context.sea_level) as f32;
             }
         }
     }
 
-    ids
+    (ids, densities)
 }
 
-pub fn generate_noise_map<'a, T: ConstShape<2, Coord = usize>>(
+/// Samples a `width * depth` noise map starting at the world position `[x, z]`.
+pub fn generate_noise_map(
+    noise_data: &NoiseData,
     seed: u32,
-    octaves: usize,
-    persistance: f64,
-    lacunarity: f64,
+    [x, z]: [i32; 2],
+    [width, depth]: [usize; 2],
 ) -> NoiseMap {
-    let fbm = Fbm::<Perlin>::new(seed)
-        .set_octaves(octaves)
-        .set_persistence(persistance)
-        .set_lacunarity(lacunarity);
-
-    PlaneMapBuilder::<&Fbm<Perlin>, 4>::new(&fbm)
-        .set_size(T::ARRAY[0], T::ARRAY[1])
-        .build()
+    let fbm = create_fbm(noise_data, seed);
+    let mut map = NoiseMap::new(width, depth);
+
+    for map_z in 0..depth {
+        for map_x in 0..width {
+            let value = sample_height(&fbm, noise_data, x + map_x as i32, z + map_z as i32);
+
+            map.set_value(map_x, map_z, value);
+        }
+    }
+
+    map
+}
+
+pub fn create_fbm(noise_data: &NoiseData, seed: u32) -> Fbm<Perlin> {
+    Fbm::<Perlin>::new(seed)
+        .set_octaves(noise_data.octaves)
+        .set_persistence(noise_data.persistence)
+        .set_lacunarity(noise_data.lacunarity)
+}
+
+/// Samples the raw terrain noise of the block column at world position `(x, z)`.
+pub fn sample_height(fbm: &Fbm<Perlin>, noise_data: &NoiseData, x: i32, z: i32) -> f64 {
+    fbm.get([x as f64 * noise_data.scale, z as f64 * noise_data.scale])
+}
+
+#[cfg(test)]
+mod test {
+    use ndshape::{ConstShape, ConstShape3u32};
+
+    use super::{generate_noise_map, generate_terrain_3d, DensitySampler, NoiseData};
+    use crate::{
+        chunk::ChunkShape,
+        terrain::{biome::Biomes, BiomeTerrainGenerator},
+    };
+
+    #[test]
+    pub fn noise_map_border_test() {
+        let noise_data = NoiseData::new();
+        let seed = 42;
+
+        let wide = generate_noise_map(&noise_data, seed, [-32, 64], [64, 32]);
+        let left = generate_noise_map(&noise_data, seed, [-32, 64], [32, 32]);
+        let right = generate_noise_map(&noise_data, seed, [0, 64], [32, 32]);
+
+        for z in 0..32 {
+            for x in 0..32 {
+                assert_eq!(wide[(x, z)], left[(x, z)]);
+                assert_eq!(wide[(x + 32, z)], right[(x, z)]);
+            }
+        }
+    }
+
+    #[test]
+    pub fn terrain_border_test() {
+        type WideShape = ConstShape3u32<64, 32, 32>;
+        type TallShape = ConstShape3u32<32, 64, 32>;
+
+        let noise_data = NoiseData::new();
+        let biomes = Biomes::default();
+        let seed = 1337;
+        let generate = |origin| {
+            generate_terrain_3d::<ChunkShape>(
+                &noise_data,
+                seed,
+                &biomes,
+                origin,
+                BiomeTerrainGenerator::default(),
+            )
+        };
+
+        let wide = generate_terrain_3d::<WideShape>(
+            &noise_data,
+            seed,
+            &biomes,
+            [32, 0, -96],
+            BiomeTerrainGenerator::default(),
+        );
+        let tall = generate_terrain_3d::<TallShape>(
+            &noise_data,
+            seed,
+            &biomes,
+            [32, -32, -96],
+            BiomeTerrainGenerator::default(),
+        );
+
+        let left = generate([32, 0, -96]);
+        let right = generate([64, 0, -96]);
+        let below = generate([32, -32, -96]);
+
+        for i in 0..ChunkShape::SIZE {
+            let [x, y, z] = ChunkShape::delinearize(i);
+
+            assert_eq!(
+                wide[WideShape::linearize([x, y, z]) as usize],
+                left[i as usize]
+            );
+            assert_eq!(
+                wide[WideShape::linearize([x + 32, y, z]) as usize],
+                right[i as usize]
+            );
+            assert_eq!(
+                tall[TallShape::linearize([x, y, z]) as usize],
+                below[i as usize]
+            );
+            assert_eq!(
+                tall[TallShape::linearize([x, y + 32, z]) as usize],
+                left[i as usize]
+            );
+        }
+    }
+
+    #[test]
+    pub fn terrain_reproducibility_test() {
+        let noise_data = NoiseData::new();
+        let biomes = Biomes::default();
+
+        for origin in [[0, 0, 0], [-64, -32, 32], [4096, 64, -8192]] {
+            let first = generate_terrain_3d::<ChunkShape>(
+                &noise_data,
+                7,
+                &biomes,
+                origin,
+                BiomeTerrainGenerator::default(),
+            );
+            let second = generate_terrain_3d::<ChunkShape>(
+                &noise_data,
+                7,
+                &biomes,
+                origin,
+                BiomeTerrainGenerator::default(),
+            );
+
+            assert_eq!(first, second);
+        }
+
+        let first = generate_noise_map(&noise_data, 7, [16, 16], [32, 32]);
+        let other = generate_noise_map(&noise_data, 8, [16, 16], [32, 32]);
+
+        assert!(first.iter().zip(other.iter()).any(|(a, b)| a != b));
+    }
+
+    #[test]
+    pub fn caves_test() {
+        type DeepShape = ConstShape3u32<64, 64, 64>;
+
+        let biomes = Biomes::default();
+        let generate = |noise_data: &NoiseData, origin| {
+            generate_terrain_3d::<DeepShape>(
+                noise_data,
+                3,
+                &biomes,
+                origin,
+                BiomeTerrainGenerator::default(),
+            )
+        };
+
+        let solid = NoiseData {
+            caves: false,
+            ..NoiseData::new()
+        };
+
+        // far below the surface, everything is solid unless caves are carved
+        assert!(generate(&solid, [0, -160, 0]).iter().all(|id| *id != 0));
+        assert!(generate(&NoiseData::new(), [0, -160, 0]).contains(&0));
+
+        // around the surface, some columns have air below solid ground
+        let terrain = generate(&solid, [0, -32, 0]);
+        let overhangs = (0..DeepShape::SIZE)
+            .map(DeepShape::delinearize)
+            .filter(|[x, y, z]| {
+                *y > 0
+                    && terrain[DeepShape::linearize([*x, *y, *z]) as usize] != 0
+                    && terrain[DeepShape::linearize([*x, y - 1, *z]) as usize] == 0
+            })
+            .count();
+
+        assert!(overhangs > 0);
+
+        let density = DensitySampler::new(&solid, 3);
+
+        assert!((0..64).all(|y| !density.carved([5, y, 7])));
+    }
+
+    #[test]
+    pub fn density_test() {
+        let noise_data = NoiseData::new();
+        let biomes = Biomes::default();
+        let sampler = biomes.sampler(&noise_data, 5);
+        let density = DensitySampler::new(&noise_data, 5);
+        let mut crossings = 0;
+
+        for z in 0..8 {
+            for x in 0..8 {
+                let column = sampler.sample(x, z);
+
+                for y in -64..64 {
+                    let position = [x, y, z];
+                    let surface = density.surface(position, column.height);
+                    let value = density.density(position, surface, column.sea_level);
+
+                    assert_eq!(value <= 0.0, density.is_ground(position, &column));
+
+                    // the top block of the ground knows how far into it the surface lies
+                    if value < 0.0 && value > -1.0 {
+                        crossings += 1;
+                    }
+                }
+            }
+        }
+
+        assert!(crossings > 0);
+    }
+
+    #[test]
+    pub fn top_ground_test() {
+        type ColumnShape = ConstShape3u32<16, 128, 16>;
+
+        let noise_data = NoiseData::new();
+        let biomes = Biomes::default();
+        let generator = BiomeTerrainGenerator::default();
+        let origin = [0, -64, 0];
+        let terrain =
+            generate_terrain_3d::<ColumnShape>(&noise_data, 8, &biomes, origin, generator);
+        let sampler = biomes.sampler(&noise_data, 8);
+        let density = DensitySampler::new(&noise_data, 8);
+        let solid = |x: u32, y: i32, z: u32| {
+            let block = terrain[ColumnShape::linearize([x, (y - origin[1]) as u32, z]) as usize];
+
+            block != 0 && block != generator.water
+        };
+
+        let mut found = 0;
+
+        for z in 0..16 {
+            for x in 0..16 {
+                let column = sampler.sample(x as i32, z as i32);
+
+                // columns a cave opened up deep below the surface have no ground to stand on
+                let Some(top) = density.top_ground([x as i32, z as i32], &column) else {
+                    continue;
+                };
+
+                found += 1;
+
+                // the top is part of the generated terrain, and nothing above it is more than
+                // a single decoration block
+                assert!(solid(x, top, z), "no ground at {x}, {top}, {z}");
+                assert!((top + 2..64).all(|y| !(solid(x, y, z) && solid(x, y - 1, z))));
+            }
+        }
+
+        assert!(found > 0);
+    }
 }
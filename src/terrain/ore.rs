@@ -0,0 +1,310 @@
+use bevy::{log::warn, prelude::Resource, utils::HashMap};
+use ndshape::ConstShape;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    chunk::{container::ChunkKey, storage::ChunkStorage},
+    material::Materials,
+};
+
+/// A kind of ore vein, resolved from the [`OreSettings`](crate::material::OreSettings) of a material.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OreVein {
+    pub block: u8,
+    pub host: u8,
+    /// Lowest and highest world y, inclusive.
+    pub depth: (i32, i32),
+    pub size: u32,
+    pub frequency: f64,
+}
+
+/// Replaces host blocks of freshly generated chunks with ore veins.
+///
+/// Veins only depend on the world seed and the chunk's origin, so a chunk always gets the same
+/// ores no matter when it is generated. They stay inside the chunk they start in.
+#[derive(Clone, Debug, Default)]
+pub struct OrePass {
+    veins: Vec<OreVein>,
+}
+
+impl OrePass {
+    pub fn new(mut veins: Vec<OreVein>) -> Self {
+        // materials come out of a hash map, sort them so veins are always placed in the same order
+        veins.sort_by_key(|vein| vein.block);
+
+        Self { veins }
+    }
+
+    /// Collects the veins of every material with ore settings, skipping ones with an unknown host.
+    pub fn from_materials(materials: &Materials) -> Self {
+        let veins = materials
+            .iter()
+            .filter_map(|material| {
+                let settings = material.ore.as_ref()?;
+                let Some(host) = materials.id_of(&settings.host) else {
+                    warn!(
+                        "ore {:?} has no host material named {:?}",
+                        material.name, settings.host
+                    );
+                    return None;
+                };
+
+                Some(OreVein {
+                    block: material.id,
+                    host,
+                    depth: settings.depth,
+                    size: settings.vein_size,
+                    frequency: settings.frequency,
+                })
+            })
+            .collect();
+
+        Self::new(veins)
+    }
+
+    pub fn veins(&self) -> &[OreVein] {
+        &self.veins
+    }
+
+    /// Places veins into the `blocks` of the chunk whose minimum corner sits at `origin`, laid
+    /// out the same way as `T`, and returns how many blocks of each ore were placed.
+    pub fn apply<T: ConstShape<3, Coord = u32>>(
+        &self,
+        seed: u32,
+        origin: [i32; 3],
+        blocks: &mut [u8],
+    ) -> OreStats {
+        let size = T::ARRAY.map(|axis| axis as i32);
+        let mut stats = OreStats::default();
+
+        for vein in &self.veins {
+            // the part of the chunk within the vein's depth range, in chunk coordinates
+            let bottom = (vein.depth.0 - origin[1]).max(0);
+            let top = (vein.depth.1 - origin[1]).min(size[1] - 1);
+
+            if bottom > top {
+                continue;
+            }
+
+            let mut rng = StdRng::seed_from_u64(vein_seed(seed, origin, vein.block));
+            let coverage = (top - bottom + 1) as f64 / size[1] as f64;
+            let expected = vein.frequency * coverage;
+            let count = expected as u32 + u32::from(rng.gen_bool(expected.fract()));
+
+            for _ in 0..count {
+                let mut position = [
+                    rng.gen_range(0..size[0]),
+                    rng.gen_range(bottom..=top),
+                    rng.gen_range(0..size[2]),
+                ];
+
+                for _ in 0..vein.size {
+                    let inside = (0..3).all(|axis| (0..size[axis]).contains(&position[axis]))
+                        && (bottom..=top).contains(&position[1]);
+
+                    if inside {
+                        let index = T::linearize(position.map(|v| v as u32)) as usize;
+
+                        if blocks[index] == vein.host {
+                            blocks[index] = vein.block;
+                            stats.add(vein.block, 1);
+                        }
+                    }
+
+                    position[rng.gen_range(0..3)] += if rng.gen_bool(0.5) { 1 } else { -1 };
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Counts the blocks of every ore in a chunk's storage.
+    pub fn count(&self, storage: &ChunkStorage) -> OreStats {
+        let mut stats = OreStats::default();
+
+        for vein in &self.veins {
+            stats.add(vein.block, storage.count(vein.block));
+        }
+
+        stats
+    }
+}
+
+/// Mixes the world seed, chunk origin and ore into the seed of the vein random generator.
+fn vein_seed(seed: u32, [x, y, z]: [i32; 3], block: u8) -> u64 {
+    [x as u32, y as u32, z as u32, block as u32]
+        .into_iter()
+        .fold(seed as u64, |hash, value| {
+            (hash ^ value as u64)
+                .wrapping_mul(0x0100_0000_01b3)
+                .rotate_left(29)
+        })
+}
+
+/// Number of blocks per ore id.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OreStats {
+    counts: HashMap<u8, u32>,
+}
+
+impl OreStats {
+    pub fn add(&mut self, block: u8, count: u32) {
+        if count > 0 {
+            *self.counts.entry(block).or_default() += count;
+        }
+    }
+
+    pub fn get(&self, block: u8) -> u32 {
+        self.counts.get(&block).copied().unwrap_or(0)
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.values().sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, u32)> + '_ {
+        self.counts.iter().map(|(block, count)| (*block, *count))
+    }
+
+    pub fn merge(&mut self, other: &OreStats) {
+        for (block, count) in other.iter() {
+            self.add(block, count);
+        }
+    }
+}
+
+/// Ore counts of every loaded chunk, for balancing ore settings.
+#[derive(Resource, Default, Debug)]
+pub struct OreStatistics {
+    chunks: HashMap<ChunkKey, OreStats>,
+}
+
+impl OreStatistics {
+    pub fn record(&mut self, key: ChunkKey, stats: OreStats) {
+        self.chunks.insert(key, stats);
+    }
+
+    pub fn get(&self, key: ChunkKey) -> Option<&OreStats> {
+        self.chunks.get(&key)
+    }
+
+    /// Forgets every chunk whose key doesn't match `keep`.
+    pub fn retain(&mut self, keep: impl Fn(ChunkKey) -> bool) {
+        self.chunks.retain(|key, _| keep(*key));
+    }
+
+    /// Number of chunks with recorded statistics.
+    pub fn chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Ore counts summed over every recorded chunk.
+    pub fn totals(&self) -> OreStats {
+        let mut totals = OreStats::default();
+
+        for stats in self.chunks.values() {
+            totals.merge(stats);
+        }
+
+        totals
+    }
+
+    /// Average blocks of `block` per recorded chunk.
+    pub fn average(&self, block: u8) -> f64 {
+        if self.chunks.is_empty() {
+            return 0.0;
+        }
+
+        self.totals().get(block) as f64 / self.chunks.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::IVec3;
+    use ndshape::ConstShape;
+
+    use super::{OrePass, OreStatistics, OreVein};
+    use crate::{
+        chunk::{storage::ChunkStorage, ChunkShape},
+        material::Materials,
+    };
+
+    #[test]
+    pub fn ore_placement_test() {
+        let pass = OrePass::from_materials(&Materials::default());
+        let coal = pass.veins().iter().find(|vein| vein.block == 5).unwrap();
+
+        assert_eq!(coal.host, 2);
+        assert_eq!(pass.veins().len(), 2);
+
+        let generate = |seed, origin| {
+            let mut blocks = vec![2; ChunkShape::SIZE as usize];
+            let stats = pass.apply::<ChunkShape>(seed, origin, &mut blocks);
+
+            (blocks, stats)
+        };
+
+        let (blocks, stats) = generate(9, [0, -64, 0]);
+
+        assert!(stats.total() > 0);
+        assert_eq!(generate(9, [0, -64, 0]).0, blocks);
+        assert_ne!(generate(10, [0, -64, 0]).0, blocks);
+        assert_ne!(generate(9, [32, -64, 0]).0, blocks);
+
+        // the reported counts match what ended up in the chunk
+        let storage = ChunkStorage::from_blocks(&blocks);
+
+        assert_eq!(pass.count(&storage), stats);
+
+        // veins never leave their host block or depth range
+        let (air, stats) = {
+            let mut blocks = vec![0; ChunkShape::SIZE as usize];
+            let stats = pass.apply::<ChunkShape>(9, [0, -64, 0], &mut blocks);
+
+            (blocks, stats)
+        };
+
+        assert!(air.iter().all(|id| *id == 0));
+        assert_eq!(stats.total(), 0);
+        assert_eq!(generate(9, [0, 256, 0]).1.total(), 0);
+    }
+
+    #[test]
+    pub fn ore_depth_test() {
+        let pass = OrePass::new(vec![OreVein {
+            block: 9,
+            host: 2,
+            depth: (-8, -1),
+            size: 64,
+            frequency: 40.0,
+        }]);
+
+        let mut blocks = vec![2; ChunkShape::SIZE as usize];
+        let stats = pass.apply::<ChunkShape>(1, [0, -32, 0], &mut blocks);
+
+        assert!(stats.get(9) > 0);
+
+        for i in 0..ChunkShape::SIZE {
+            let [_, y, _] = ChunkShape::delinearize(i);
+
+            if blocks[i as usize] == 9 {
+                assert!((24..32).contains(&y));
+            }
+        }
+
+        let mut statistics = OreStatistics::default();
+
+        statistics.record(IVec3::ZERO, stats.clone());
+        statistics.record(IVec3::X, Default::default());
+
+        assert_eq!(statistics.totals(), stats);
+        assert_eq!(statistics.average(9), stats.get(9) as f64 / 2.0);
+
+        statistics.retain(|key| key == IVec3::X);
+
+        assert_eq!(statistics.chunks(), 1);
+        assert_eq!(statistics.totals().total(), 0);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use bevy::{
+    app::AppExit,
+    log::error,
+    prelude::{CoreStage, Entity, EventReader, Plugin, Res, Resource},
+    tasks::{IoTaskPool, Task},
+    utils::HashMap,
+};
+use parking_lot::Mutex;
+
+use crate::chunk::{
+    container::{ChunkKey, Chunks},
+    storage::ChunkStorage,
+};
+
+use self::region::{RegionFile, RegionKey};
+
+pub mod region;
+
+/// Reads and writes chunks from the region files of a world directory.
+///
+/// Regions are cached once read, so it's cheap to clone and share this with the generation threads.
+#[derive(Resource, Clone)]
+pub struct WorldStorage {
+    directory: PathBuf,
+    regions: Arc<Mutex<HashMap<RegionKey, RegionFile>>>,
+    /// Chunks handed to [`WorldStorage::save_chunks_in_background`] that aren't written yet,
+    /// loads read them from here in the meantime.
+    unwritten: Arc<Mutex<HashMap<ChunkKey, Arc<ChunkStorage>>>>,
+}
+
+impl WorldStorage {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            regions: Default::default(),
+            unwritten: Default::default(),
+        }
+    }
+
+    pub fn load_chunk(&self, key: ChunkKey) -> io::Result<Option<ChunkStorage>> {
+        if let Some(storage) = self.unwritten.lock().get(&key) {
+            return Ok(Some(storage.as_ref().clone()));
+        }
+
+        let (region, index) = RegionFile::locate(key);
+        let mut regions = self.regions.lock();
+
+        self.region(&mut regions, region)?.get(index)
+    }
+
+    /// Writes the given chunks and flushes every region file they belong to.
+    ///
+    /// A region that can't be read or written is logged and skipped, the others are saved
+    /// anyway. Returns the regions that failed.
+    pub fn save_chunks<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (ChunkKey, &'a ChunkStorage)>,
+    ) -> Vec<RegionKey> {
+        let mut by_region = HashMap::<RegionKey, Vec<_>>::default();
+
+        for (key, storage) in chunks {
+            let (region, index) = RegionFile::locate(key);
+
+            by_region.entry(region).or_default().push((index, storage));
+        }
+
+        let mut regions = self.regions.lock();
+        let mut failed = Vec::new();
+
+        for (region, chunks) in by_region {
+            if let Err(error) = self.save_region(&mut regions, region, chunks) {
+                error!("failed to save {}: {error}", RegionFile::file_name(region));
+                failed.push(region);
+            }
+        }
+
+        failed
+    }
+
+    /// Saves the given chunks on the [`IoTaskPool`], they are loaded back from memory until
+    /// they are written. Chunks of regions that fail to save stay there.
+    pub fn save_chunks_in_background(&self, chunks: Vec<(ChunkKey, ChunkStorage)>) -> Task<()> {
+        let chunks = chunks
+            .into_iter()
+            .map(|(key, storage)| (key, Arc::new(storage)))
+            .collect::<Vec<_>>();
+
+        self.unwritten.lock().extend(chunks.iter().cloned());
+
+        let storage = self.clone();
+
+        IoTaskPool::get().spawn(async move {
+            let failed =
+                storage.save_chunks(chunks.iter().map(|(key, chunk)| (*key, chunk.as_ref())));
+            let mut unwritten = storage.unwritten.lock();
+
+            for (key, chunk) in chunks {
+                let written = !failed.contains(&RegionFile::locate(key).0);
+
+                // a newer save of the same chunk may have been queued in the meantime
+                if written
+                    && unwritten
+                        .get(&key)
+                        .is_some_and(|queued| Arc::ptr_eq(queued, &chunk))
+                {
+                    unwritten.remove(&key);
+                }
+            }
+        })
+    }
+
+    fn save_region(
+        &self,
+        regions: &mut HashMap<RegionKey, RegionFile>,
+        region: RegionKey,
+        chunks: Vec<(usize, &ChunkStorage)>,
+    ) -> io::Result<()> {
+        let file = self.region(regions, region)?;
+
+        for (index, storage) in chunks {
+            file.set(index, storage)?;
+        }
+
+        fs::create_dir_all(&self.directory)?;
+
+        let path = self.directory.join(RegionFile::file_name(region));
+        let temporary = path.with_extension("region.tmp");
+
+        file.write(BufWriter::new(File::create(&temporary)?))?;
+        fs::rename(temporary, path)
+    }
+
+    fn region<'a>(
+        &self,
+        regions: &'a mut HashMap<RegionKey, RegionFile>,
+        region: RegionKey,
+    ) -> io::Result<&'a mut RegionFile> {
+        if !regions.contains_key(&region) {
+            let path = self.directory.join(RegionFile::file_name(region));
+
+            let file = match File::open(path) {
+                Ok(file) => RegionFile::read(BufReader::new(file))?,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => RegionFile::default(),
+                Err(error) => return Err(error),
+            };
+
+            regions.insert(region, file);
+        }
+
+        Ok(regions.get_mut(&region).unwrap())
+    }
+}
+
+impl Chunks {
+    /// Removes the chunks at `keys`, saving the ones with unsaved edits in the background
+    /// first, and returns their entities to despawn.
+    ///
+    /// Chunks coming back into range are loaded from the save or generated again.
+    pub fn unload(
+        &mut self,
+        keys: impl IntoIterator<Item = ChunkKey>,
+        storage: &WorldStorage,
+    ) -> Vec<Entity> {
+        let mut unsaved = Vec::new();
+        let mut entities = Vec::new();
+
+        for key in keys {
+            let Some(chunk) = self.remove(key) else {
+                continue;
+            };
+
+            entities.extend(
+                [chunk.entity, chunk.transparent_entity, chunk.collider]
+                    .into_iter()
+                    .flatten(),
+            );
+
+            if chunk.unsaved {
+                unsaved.push((key, chunk.blocks().clone()));
+            }
+        }
+
+        // compressing and writing whole region files would stall the frame
+        if !unsaved.is_empty() {
+            storage.save_chunks_in_background(unsaved).detach();
+        }
+
+        entities
+    }
+}
+
+pub struct WorldStoragePlugin;
+
+impl WorldStoragePlugin {
+    pub fn save_on_exit(
+        mut exit: EventReader<AppExit>,
+        chunks: Res<Chunks>,
+        storage: Res<WorldStorage>,
+    ) {
+        if exit.iter().next().is_none() {
+            return;
+        }
+
+        let edited = chunks
+            .iter()
+            .filter(|chunk| chunk.unsaved)
+            .map(|chunk| (chunk.position, chunk.blocks()));
+
+        // the app is about to close, so this can't wait for a background task
+        storage.save_chunks(edited);
+    }
+}
+
+impl Plugin for WorldStoragePlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        app.add_system_to_stage(CoreStage::Last, Self::save_on_exit);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, thread, time::Duration};
+
+    use bevy::{
+        prelude::IVec3,
+        tasks::{IoTaskPool, TaskPool},
+    };
+    use futures_lite::future;
+    use ndshape::ConstShape;
+
+    use super::{region::RegionFile, WorldStorage};
+    use crate::chunk::{
+        container::{ChunkKey, Chunks, DomainChunk},
+        storage::ChunkStorage,
+        ChunkShape,
+    };
+
+    #[test]
+    pub fn world_storage_round_trip_test() {
+        let directory = env::temp_dir().join(format!("voxel-world-{}", std::process::id()));
+        let blocks = (0..ChunkShape::SIZE)
+            .map(|i| (i % 3) as u8)
+            .collect::<Vec<_>>();
+        let paletted = ChunkStorage::from_blocks(&blocks);
+        let uniform = ChunkStorage::Uniform(4);
+
+        let storage = WorldStorage::new(&directory);
+
+        assert!(storage.load_chunk(ChunkKey::ZERO).unwrap().is_none());
+
+        assert!(storage
+            .save_chunks([
+                (ChunkKey::new(-1, 2, 40), &paletted),
+                (ChunkKey::new(0, 0, 0), &uniform),
+            ])
+            .is_empty());
+
+        // a fresh storage has nothing cached and reads back from disk
+        let storage = WorldStorage::new(&directory);
+
+        let read = storage
+            .load_chunk(ChunkKey::new(-1, 2, 40))
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.to_vec(), blocks);
+
+        let read = storage.load_chunk(ChunkKey::ZERO).unwrap().unwrap();
+        assert!(matches!(read, ChunkStorage::Uniform(4)));
+
+        assert!(storage
+            .load_chunk(ChunkKey::new(-1, 3, 40))
+            .unwrap()
+            .is_none());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    pub fn broken_region_test() {
+        let directory = env::temp_dir().join(format!("voxel-broken-{}", std::process::id()));
+        let (broken, _) = RegionFile::locate(ChunkKey::ZERO);
+
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join(RegionFile::file_name(broken)), b"nope").unwrap();
+
+        // the unreadable region is skipped, the other one is still written
+        let storage = WorldStorage::new(&directory);
+        let failed = storage.save_chunks([
+            (ChunkKey::ZERO, &ChunkStorage::Uniform(2)),
+            (ChunkKey::new(0, 1, 0), &ChunkStorage::Uniform(3)),
+        ]);
+
+        assert_eq!(failed, vec![broken]);
+
+        let storage = WorldStorage::new(&directory);
+        let read = storage.load_chunk(ChunkKey::new(0, 1, 0)).unwrap().unwrap();
+
+        assert!(matches!(read, ChunkStorage::Uniform(3)));
+        assert!(storage.load_chunk(ChunkKey::ZERO).is_err());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    pub fn background_save_test() {
+        IoTaskPool::init(TaskPool::default);
+
+        let directory = env::temp_dir().join(format!("voxel-background-{}", std::process::id()));
+        let storage = WorldStorage::new(&directory);
+        let task =
+            storage.save_chunks_in_background(vec![(ChunkKey::ZERO, ChunkStorage::Uniform(5))]);
+
+        // the chunk can be loaded again before it's written, and from disk afterwards
+        let read = storage.load_chunk(ChunkKey::ZERO).unwrap().unwrap();
+
+        assert!(matches!(read, ChunkStorage::Uniform(5)));
+
+        future::block_on(task);
+
+        assert!(storage.unwritten.lock().is_empty());
+
+        let read = WorldStorage::new(&directory)
+            .load_chunk(ChunkKey::ZERO)
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(read, ChunkStorage::Uniform(5)));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    pub fn unload_edits_test() {
+        IoTaskPool::init(TaskPool::default);
+
+        let directory = env::temp_dir().join(format!("voxel-unload-{}", std::process::id()));
+        let storage = WorldStorage::new(&directory);
+        let mut chunks = Chunks::default();
+        let key = ChunkKey::new(1, -1, 2);
+
+        // one chunk the player dug into and one that was only generated
+        chunks
+            .get_domain_at_mut(key.to_array())
+            .override_blocks(ChunkStorage::Uniform(2));
+        chunks.get_domain_at_mut([0, 0, 0]);
+        chunks.set_block_world(IVec3::new(40, -20, 70), 0);
+
+        // a reset unloads everything and asks for the same chunks again straight away
+        assert!(chunks.unload([key, ChunkKey::ZERO], &storage).is_empty());
+        assert!(chunks.iter().next().is_none());
+
+        let edited = |storage: &WorldStorage| {
+            let blocks = storage.load_chunk(key).unwrap().unwrap();
+
+            (
+                blocks.get(ChunkShape::linearize([8, 12, 6]) as usize),
+                blocks.get(0),
+            )
+        };
+
+        assert_eq!(edited(&storage), (0, 2));
+        assert!(storage.load_chunk(ChunkKey::ZERO).unwrap().is_none());
+
+        // and the edit ends up on disk
+        for _ in 0..500 {
+            if storage.unwritten.lock().is_empty() {
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(edited(&WorldStorage::new(&directory)), (0, 2));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
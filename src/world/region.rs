@@ -0,0 +1,229 @@
+use std::io::{self, Read, Write};
+
+use bevy::prelude::IVec3;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::chunk::{container::ChunkKey, storage::ChunkStorage};
+
+/// Width and depth of a region in chunks. A region covers a single layer of chunks.
+pub const REGION_SIZE: i32 = 32;
+pub const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE) as usize;
+
+/// Bumped whenever the layout of region files changes.
+pub const REGION_VERSION: u32 = 1;
+pub const REGION_MAGIC: [u8; 4] = *b"VXRG";
+
+const HEADER_SIZE: usize = REGION_MAGIC.len() + 4 + REGION_CHUNKS * 8;
+
+/// Position of a region, i.e. chunk x and z divided by [`REGION_SIZE`] and the chunk y.
+pub type RegionKey = IVec3;
+
+/// A region file holds up to `REGION_SIZE * REGION_SIZE` chunks.
+///
+/// The file starts with [`REGION_MAGIC`], the format version and an offset table with an
+/// `(offset, length)` pair of little endian `u32`s per chunk, where a zero offset marks a
+/// missing chunk. Every present chunk is stored as a zlib compressed [`ChunkStorage`].
+#[derive(Debug, Clone)]
+pub struct RegionFile {
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl Default for RegionFile {
+    fn default() -> Self {
+        Self {
+            chunks: vec![None; REGION_CHUNKS],
+        }
+    }
+}
+
+impl RegionFile {
+    /// Returns the region containing the chunk at `key` and the chunk's index inside it.
+    pub fn locate(key: ChunkKey) -> (RegionKey, usize) {
+        let region = RegionKey::new(
+            key.x.div_euclid(REGION_SIZE),
+            key.y,
+            key.z.div_euclid(REGION_SIZE),
+        );
+        let index = key.x.rem_euclid(REGION_SIZE) + key.z.rem_euclid(REGION_SIZE) * REGION_SIZE;
+
+        (region, index as usize)
+    }
+
+    pub fn file_name(region: RegionKey) -> String {
+        format!("r.{}.{}.{}.region", region.x, region.y, region.z)
+    }
+
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < HEADER_SIZE || bytes[..4] != REGION_MAGIC {
+            return Err(invalid_data("not a region file"));
+        }
+
+        let version = read_u32(&bytes, 4);
+
+        if version != REGION_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported region version {version}, expected {REGION_VERSION}"
+            )));
+        }
+
+        let mut region = Self::default();
+
+        for (index, chunk) in region.chunks.iter_mut().enumerate() {
+            let entry = 8 + index * 8;
+            let offset = read_u32(&bytes, entry) as usize;
+            let length = read_u32(&bytes, entry + 4) as usize;
+
+            if offset == 0 {
+                continue;
+            }
+
+            let payload = bytes
+                .get(offset..offset + length)
+                .ok_or_else(|| invalid_data("chunk payload out of bounds"))?;
+
+            *chunk = Some(payload.to_vec());
+        }
+
+        Ok(region)
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        let mut offset = HEADER_SIZE;
+
+        header.extend_from_slice(&REGION_MAGIC);
+        header.extend_from_slice(&REGION_VERSION.to_le_bytes());
+
+        for chunk in &self.chunks {
+            let (start, length) = match chunk {
+                Some(payload) => (offset, payload.len()),
+                None => (0, 0),
+            };
+
+            header.extend_from_slice(&(start as u32).to_le_bytes());
+            header.extend_from_slice(&(length as u32).to_le_bytes());
+            offset += length;
+        }
+
+        writer.write_all(&header)?;
+
+        for payload in self.chunks.iter().flatten() {
+            writer.write_all(payload)?;
+        }
+
+        writer.flush()
+    }
+
+    pub fn get(&self, index: usize) -> io::Result<Option<ChunkStorage>> {
+        let Some(payload) = &self.chunks[index] else {
+            return Ok(None);
+        };
+
+        let mut bytes = Vec::new();
+        ZlibDecoder::new(payload.as_slice()).read_to_end(&mut bytes)?;
+
+        ChunkStorage::from_bytes(&bytes)
+            .map(Some)
+            .ok_or_else(|| invalid_data("malformed chunk payload"))
+    }
+
+    pub fn set(&mut self, index: usize, storage: &ChunkStorage) -> io::Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&storage.to_bytes())?;
+
+        self.chunks[index] = Some(encoder.finish()?);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::ErrorKind;
+
+    use ndshape::ConstShape;
+
+    use super::{RegionFile, REGION_CHUNKS, REGION_VERSION};
+    use crate::chunk::{container::ChunkKey, storage::ChunkStorage, ChunkShape};
+
+    #[test]
+    pub fn region_locate_test() {
+        assert_eq!(
+            RegionFile::locate(ChunkKey::new(0, 0, 0)),
+            (ChunkKey::ZERO, 0)
+        );
+        assert_eq!(
+            RegionFile::locate(ChunkKey::new(33, -4, 2)),
+            (ChunkKey::new(1, -4, 0), 1 + 2 * 32)
+        );
+        assert_eq!(
+            RegionFile::locate(ChunkKey::new(-1, 7, -32)),
+            (ChunkKey::new(-1, 7, -1), 31)
+        );
+    }
+
+    #[test]
+    pub fn region_round_trip_test() {
+        let mut region = RegionFile::default();
+        let paletted = ChunkStorage::from_blocks(
+            &(0..ChunkShape::SIZE)
+                .map(|i| (i % 7) as u8)
+                .collect::<Vec<_>>(),
+        );
+
+        region.set(0, &ChunkStorage::Uniform(2)).unwrap();
+        region.set(517, &paletted).unwrap();
+        region
+            .set(REGION_CHUNKS - 1, &ChunkStorage::Uniform(0))
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        region.write(&mut bytes).unwrap();
+
+        let read = RegionFile::read(bytes.as_slice()).unwrap();
+
+        assert_eq!(read.len(), 3);
+        assert!(matches!(
+            read.get(0).unwrap(),
+            Some(ChunkStorage::Uniform(2))
+        ));
+        assert_eq!(read.get(517).unwrap().unwrap().to_vec(), paletted.to_vec());
+        assert!(matches!(
+            read.get(REGION_CHUNKS - 1).unwrap(),
+            Some(ChunkStorage::Uniform(0))
+        ));
+        assert!(read.get(1).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn region_version_test() {
+        let mut bytes = Vec::new();
+        RegionFile::default().write(&mut bytes).unwrap();
+
+        bytes[4..8].copy_from_slice(&(REGION_VERSION + 1).to_le_bytes());
+
+        let error = RegionFile::read(bytes.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        let error = RegionFile::read(&b"nope"[..]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}